@@ -0,0 +1,269 @@
+use crate::*;
+use near_sdk::json_types::{U128, U64};
+
+/// Longest bidding a listing can ask for.
+pub const MAX_BIDDING_DURATION_SEC: u64 = 30 * 24 * 60 * 60;
+
+/// What lenders compete on: a lower APR for the listed principal, or a higher
+/// principal at the listed APR.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub enum AuctionKind {
+    Apr,
+    Principal,
+}
+
+/// Auction parameters of a `ListingRequest`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AuctionRequest {
+    pub kind: AuctionKind,
+    /// Seconds from listing until the best bid is accepted automatically.
+    pub bidding_duration: u64,
+}
+
+impl AuctionRequest {
+    pub fn validate(&self) {
+        if self.bidding_duration == 0 || self.bidding_duration > MAX_BIDDING_DURATION_SEC {
+            ContractError::InvalidArgument(format!("Auction bidding duration must be within (0, {}] seconds.",
+                                                   MAX_BIDDING_DURATION_SEC)).panic();
+        }
+    }
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Bid {
+    pub bidder: AccountId,
+    pub principal: U128,
+    pub apr_bps: u64,
+}
+
+/// Bidding state of an auction listing. The best bid's principal is held by
+/// the contract until it is outbid, accepted or the listing is withdrawn.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Auction {
+    pub kind: AuctionKind,
+    pub deadline: U64,
+    pub best_bid: Option<Bid>,
+}
+
+impl Auction {
+    pub fn from_request(request: AuctionRequest) -> Self {
+        let bidding_duration = Duration::from_secs(request.bidding_duration).as_nanos() as u64;
+        Self {
+            kind: request.kind,
+            deadline: U64(env::block_timestamp() + bidding_duration),
+            best_bid: None,
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        env::block_timestamp() >= self.deadline.0
+    }
+
+    /// Refunds the best bid and returns its bidder. Once the deadline passed
+    /// the best bid is binding.
+    pub fn cancel(&mut self) -> Option<AccountId> {
+        self.best_bid.take().map(|bid| {
+            if self.is_finished() {
+                ContractError::WrongPhase("Auction has ended, its best bid is accepted. Call finalize_auction.".to_string()).panic();
+            }
+            Promise::new(bid.bidder.clone()).transfer(bid.principal.0);
+            bid.bidder
+        })
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// For `Apr` auctions `apr_bps` is the bid and the attached deposit must equal
+    /// the listed principal. For `Principal` auctions the attached deposit is
+    /// the bid and `apr_bps` is ignored.
+    #[payable]
+    pub fn place_bid(&mut self, token_id: TokenId, apr_bps: Option<u64>) {
+        let bidder = env::predecessor_account_id();
+        let deposit = env::attached_deposit();
+
+        let mut token = self.internal_get_auction_token(&token_id);
+        require(bidder != token.owner_id, || ContractError::Unauthorized("Owner can't bid on own listing.".to_string()));
+        self.internal_reserve_storage(&bidder, CREDIT_STORAGE_BYTES);
+
+        let listed_principal = token.borrowed_money;
+        let listed_apr_bps = token.apr_bps;
+        let auction = token
+            .auction
+            .as_mut()
+            .unwrap_or_else(|| ContractError::Unsupported(format!("Token {} isn't listed on auction.", token_id)).panic());
+        require(!auction.is_finished(), || ContractError::WrongPhase("Auction has ended.".to_string()));
+
+        let bid = match auction.kind {
+            AuctionKind::Apr => {
+                let apr_bps = apr_bps.unwrap_or_else(|| {
+                    ContractError::InvalidArgument("Apr auction bid must set apr_bps.".to_string()).panic()
+                });
+                require(deposit == listed_principal, || {
+                    ContractError::WrongDeposit("Apr auction bid must attach the listed principal.".to_string())
+                });
+                require(apr_bps > 0 && apr_bps <= listed_apr_bps, || {
+                    ContractError::InvalidArgument(format!("Bid apr_bps must be positive and not above listed apr_bps {}.", listed_apr_bps))
+                });
+                if let Some(best_bid) = &auction.best_bid {
+                    require(apr_bps < best_bid.apr_bps, || {
+                        ContractError::InvalidArgument(format!("Bid apr_bps must be lower than the best bid apr_bps {}.", best_bid.apr_bps))
+                    });
+                }
+                Bid { bidder, principal: U128(deposit), apr_bps }
+            }
+            AuctionKind::Principal => {
+                require(deposit >= listed_principal, || {
+                    ContractError::WrongDeposit(format!("Bid must attach at least the listed principal {}.", listed_principal))
+                });
+                if let Some(best_bid) = &auction.best_bid {
+                    require(deposit > best_bid.principal.0, || {
+                        ContractError::WrongDeposit(format!("Bid must be higher than the best bid {}.", best_bid.principal.0))
+                    });
+                }
+                Bid { bidder, principal: U128(deposit), apr_bps: listed_apr_bps }
+            }
+        };
+
+        ContractEvent::OfferCreated(OfferData::new(OfferKind::Bid,
+                                                   &token_id,
+                                                   &bid.bidder,
+                                                   bid.principal,
+                                                   bid.apr_bps,
+                                                   token.duration)).emit();
+        if let Some(outbid) = auction.best_bid.replace(bid) {
+            self.internal_release_storage(&outbid.bidder, CREDIT_STORAGE_BYTES);
+            Promise::new(outbid.bidder).transfer(outbid.principal.0);
+        }
+
+        self.token_id_to_locked_token.insert(&token_id, &token);
+    }
+
+    /// Accepts the current best bid before the auction deadline.
+    pub fn accept_bid(&mut self, token_id: TokenId) {
+        let token = self.internal_get_auction_token(&token_id);
+        require(env::predecessor_account_id() == token.owner_id, || {
+            ContractError::Unauthorized("Only owner can accept a bid.".to_string())
+        });
+
+        self.internal_accept_best_bid(token);
+    }
+
+    /// Accepts the best bid of an auction past its deadline. Callable by anyone.
+    pub fn finalize_auction(&mut self, token_id: TokenId) {
+        let token = self.internal_get_auction_token(&token_id);
+        require(token.auction.as_ref().iter().all(|auction| auction.is_finished()), || {
+            ContractError::WrongPhase("Auction is still running.".to_string())
+        });
+
+        self.internal_accept_best_bid(token);
+    }
+}
+
+impl Contract {
+    fn internal_get_auction_token(&self, token_id: &TokenId) -> LockedToken {
+        let token = self.internal_get_token(token_id);
+
+        require_state(&token, LockedTokenState::Sale);
+        require(token.auction.is_some(), || ContractError::Unsupported(format!("Token {} isn't listed on auction.", token_id)));
+        token
+    }
+
+    fn internal_accept_best_bid(&mut self, mut token: LockedToken) {
+        let bid = token
+            .auction
+            .as_mut()
+            .and_then(|auction| auction.best_bid.take())
+            .unwrap_or_else(|| ContractError::WrongPhase("Auction has no bids.".to_string()).panic());
+
+        self.internal_release_storage(&token.owner_id, LOAN_STORAGE_BYTES);
+        self.internal_release_storage(&bid.bidder, CREDIT_STORAGE_BYTES);
+        self.internal_lock_token(token, &bid.bidder, bid.principal.0, bid.apr_bps);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    fn auction_msg(kind: &str) -> String {
+        format!(r#"{{"principal": "1000000000000000000000000", "apr_bps": 1000, "duration": 3600, "title": "Listing",
+                    "auction": {{"kind": "{}", "bidding_duration": 100}}}}"#, kind)
+    }
+
+    fn setup_auction(kind: &str) -> (Contract, TokenId) {
+        let mut contract = setup_contract();
+        for account_id in &[ALICE, BOB, CHARLIE] {
+            register(&mut contract, account_id, NEAR);
+        }
+        let token_id = list_nft(&mut contract, ALICE, "1", &auction_msg(kind));
+        (contract, token_id)
+    }
+
+    #[test]
+    fn outbid_principal_is_refunded_and_best_bid_settles_at_the_deadline() {
+        let (mut contract, token_id) = setup_auction("Principal");
+        set_context(BOB, NEAR, 10);
+        contract.place_bid(token_id.clone(), None);
+        set_context(CHARLIE, 2 * NEAR, 20);
+        contract.place_bid(token_id.clone(), None);
+        assert_eq!(get_transfers(), vec![(BOB.to_string(), NEAR)]);
+
+        set_context(BOB, 0, 100);
+        contract.finalize_auction(token_id.clone());
+        assert_eq!(get_transfers(), vec![(ALICE.to_string(), 2 * NEAR)]);
+
+        let token = contract.internal_get_token(&token_id);
+        assert_eq!(token.state, LockedTokenState::Locked);
+        assert_eq!((token.creditor_id(), token.borrowed_money, token.apr_bps), (CHARLIE.to_string(), 2 * NEAR, 1000));
+        assert!(token.auction.is_none());
+    }
+
+    #[test]
+    fn owner_accepts_the_lowest_apr_before_the_deadline() {
+        let (mut contract, token_id) = setup_auction("Apr");
+        set_context(BOB, NEAR, 10);
+        contract.place_bid(token_id.clone(), Some(900));
+        set_context(CHARLIE, NEAR, 20);
+        contract.place_bid(token_id.clone(), Some(700));
+
+        set_context(ALICE, 0, 30);
+        contract.accept_bid(token_id.clone());
+        let token = contract.internal_get_token(&token_id);
+        assert_eq!((token.creditor_id(), token.borrowed_money, token.apr_bps), (CHARLIE.to_string(), NEAR, 700));
+    }
+
+    #[test]
+    #[should_panic(expected = "InvalidArgument")]
+    fn apr_bids_must_undercut_the_best_bid() {
+        let (mut contract, token_id) = setup_auction("Apr");
+        set_context(BOB, NEAR, 10);
+        contract.place_bid(token_id.clone(), Some(900));
+        set_context(CHARLIE, NEAR, 20);
+        contract.place_bid(token_id, Some(900));
+    }
+
+    #[test]
+    #[should_panic(expected = "WrongPhase")]
+    fn auction_isnt_finalized_before_the_deadline() {
+        let (mut contract, token_id) = setup_auction("Principal");
+        set_context(BOB, NEAR, 10);
+        contract.place_bid(token_id.clone(), None);
+        contract.finalize_auction(token_id);
+    }
+
+    #[test]
+    #[should_panic(expected = "WrongPhase")]
+    fn binding_bid_cant_be_withdrawn_with_the_listing() {
+        let (mut contract, token_id) = setup_auction("Principal");
+        set_context(BOB, NEAR, 10);
+        contract.place_bid(token_id.clone(), None);
+        set_context(ALICE, 1, 100);
+        contract.transfer_nft_back(token_id);
+    }
+}
@@ -0,0 +1,296 @@
+use crate::*;
+use near_sdk::serde_json::{self, Value};
+use near_sdk::StorageUsage;
+
+/// NFT transfers per bundle are bounded by the gas of a single call.
+pub const MAX_BUNDLE_SIZE: usize = 5;
+/// Bytes a draft bundle reserves of its owner's balance, enough for a draft
+/// of `MAX_BUNDLE_SIZE` NFTs.
+pub const BUNDLE_STORAGE_BYTES: StorageUsage = 1_000;
+const BUNDLE_ID_PREFIX: &str = "bundle-";
+
+/// NFTs a borrower is assembling into one collateral package. Once listed
+/// with `list_bundle` the draft becomes a `LockedToken` whose `bundle` holds
+/// the items.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CollateralBundle {
+    pub owner_id: AccountId,
+    /// `token_id:market` ids of the NFTs already held by the contract.
+    pub items: Vec<TokenId>,
+}
+
+/// `nft_approve` msg moving the approved NFT into a draft bundle.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AddToBundleRequest {
+    pub bundle_id: TokenId,
+}
+
+impl AddToBundleRequest {
+    /// Returns `None` for msgs that don't name a `bundle_id`.
+    pub fn from_msg(msg: &str) -> Option<Self> {
+        let value = serde_json::from_str::<Value>(msg).ok()?;
+        value.get("bundle_id")?;
+
+        Some(serde_json::from_value(value).unwrap_or_else(|e| {
+            ContractError::InvalidMsg(format!("Invalid bundle msg: {}", e)).panic()
+        }))
+    }
+}
+
+/// Transfers one `token_id:market` NFT held by the contract to `receiver_id`.
+pub fn transfer_bundle_item(item_id: &TokenId, receiver_id: &AccountId) -> Promise {
+    let (nft_token_id, market) = split_token_unique_id(item_id);
+    marketplace::nft_transfer(ValidAccountId::try_from(receiver_id.clone()).unwrap(),
+                              nft_token_id,
+                              None,
+                              None,
+                              &market,
+                              1,
+                              GAS_FOR_NFT_TRANSFER)
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Starts a draft bundle. NFTs are added by `nft_approve` with
+    /// `{"bundle_id": "<id>"}` as msg. The draft's storage is reserved until
+    /// it is listed or deleted.
+    pub fn create_bundle(&mut self) -> TokenId {
+        let owner_id = env::predecessor_account_id();
+        self.internal_reserve_storage(&owner_id, BUNDLE_STORAGE_BYTES);
+        let bundle_id = format!("{}{}", BUNDLE_ID_PREFIX, self.next_bundle_id);
+        self.next_bundle_id += 1;
+
+        self.bundles.insert(&bundle_id, &CollateralBundle { owner_id, items: vec![] });
+        bundle_id
+    }
+
+    /// Deletes an empty draft bundle.
+    pub fn delete_bundle(&mut self, bundle_id: TokenId) {
+        let bundle = self.internal_get_bundle(&bundle_id);
+        require(env::predecessor_account_id() == bundle.owner_id, || {
+            ContractError::Unauthorized("Only owner can delete a bundle.".to_string())
+        });
+        require(bundle.items.is_empty(), || {
+            ContractError::Conflict(format!("Bundle {} still holds NFTs.", bundle_id))
+        });
+
+        self.bundles.remove(&bundle_id);
+        self.internal_release_storage(&bundle.owner_id, BUNDLE_STORAGE_BYTES);
+    }
+
+    /// Returns an NFT of a draft bundle to its owner.
+    pub fn remove_from_bundle(&mut self, bundle_id: TokenId, item_id: TokenId) {
+        let mut bundle = self.internal_get_bundle(&bundle_id);
+        require(env::predecessor_account_id() == bundle.owner_id, || {
+            ContractError::Unauthorized("Only owner can change a bundle.".to_string())
+        });
+
+        let index = bundle
+            .items
+            .iter()
+            .position(|item| item == &item_id)
+            .unwrap_or_else(|| ContractError::NotFound(format!("{} in bundle {}", item_id, bundle_id)).panic());
+        bundle.items.remove(index);
+        self.bundles.insert(&bundle_id, &bundle);
+
+        transfer_bundle_item(&item_id, &bundle.owner_id)
+            .then(ext_self::resolve_bundle_item(bundle_id,
+                                                item_id.clone(),
+                                                bundle.owner_id.clone(),
+                                                false,
+                                                &env::current_account_id(),
+                                                0,
+                                                GAS_FOR_RESOLVE_NFT_LOCK));
+    }
+
+    /// Lists the draft bundle as collateral of a single loan. The bundle id
+    /// becomes the token id of the listing.
+    pub fn list_bundle(&mut self, bundle_id: TokenId, listing: ListingRequest) {
+        let bundle = self.internal_get_bundle(&bundle_id);
+        require(env::predecessor_account_id() == bundle.owner_id, || {
+            ContractError::Unauthorized("Only owner can list a bundle.".to_string())
+        });
+        require(!bundle.items.is_empty(), || ContractError::InvalidArgument(format!("Bundle {} is empty.", bundle_id)));
+        require(listing.market.is_none(), || {
+            ContractError::InvalidArgument("Bundle listings can't name a market.".to_string())
+        });
+        require(listing.liquidation.is_none(), || {
+            ContractError::Unsupported("Bundle collateral can't be liquidated by auction.".to_string())
+        });
+
+        listing.validate();
+        if let Some(currency) = &listing.currency {
+            self.assert_accepted_currency(currency);
+        }
+
+        self.bundles.remove(&bundle_id);
+        self.internal_release_storage(&bundle.owner_id, BUNDLE_STORAGE_BYTES);
+        let mut locked_token = LockedToken::from_listing(bundle_id, bundle.owner_id, listing);
+        locked_token.bundle = bundle.items;
+        self.internal_add_locked_token(&locked_token);
+    }
+
+    pub fn get_bundle(&self, bundle_id: TokenId) -> Option<CollateralBundle> {
+        self.bundles.get(&bundle_id)
+    }
+
+    /// Adds a received NFT to the draft, or puts back an NFT whose return to
+    /// the owner failed. An NFT arriving after the draft was listed or
+    /// filled up is sent back to the owner, the draft's limits are checked
+    /// again since other NFTs can join while a transfer is in flight.
+    #[private]
+    pub fn resolve_bundle_item(&mut self, bundle_id: TokenId, item_id: TokenId, owner_id: AccountId, added: bool) -> bool {
+        if added && !is_promise_success() {
+            env::log(format!("Failed to receive {} for bundle {}.", item_id, bundle_id).as_bytes());
+            return false;
+        }
+        if !added && is_promise_success() {
+            return true;
+        }
+
+        if let Some(mut bundle) = self.bundles.get(&bundle_id) {
+            if bundle.items.contains(&item_id) {
+                env::log(format!("Bundle {} already holds {}.", bundle_id, item_id).as_bytes());
+                return false;
+            }
+            if bundle.items.len() >= MAX_BUNDLE_SIZE {
+                env::log(format!("Bundle {} is full, returning {} to {}.", bundle_id, item_id, owner_id).as_bytes());
+                transfer_bundle_item(&item_id, &owner_id);
+                return false;
+            }
+            env::log(format!("{} kept in bundle {}.", item_id, bundle_id).as_bytes());
+            bundle.items.push(item_id);
+            self.bundles.insert(&bundle_id, &bundle);
+            true
+        } else {
+            env::log(format!("Bundle {} was already listed or deleted, returning {} to {}.", bundle_id, item_id, owner_id).as_bytes());
+            transfer_bundle_item(&item_id, &owner_id);
+            false
+        }
+    }
+}
+
+impl Contract {
+    fn internal_get_bundle(&self, bundle_id: &TokenId) -> CollateralBundle {
+        self.bundles
+            .get(bundle_id)
+            .unwrap_or_else(|| ContractError::NotFound(format!("draft bundle {}", bundle_id)).panic())
+    }
+
+    /// Pulls the approved NFT into the contract, it joins the draft in
+    /// `resolve_bundle_item`.
+    pub(crate) fn internal_add_to_bundle(
+        &mut self,
+        token_id: TokenId,
+        owner_id: AccountId,
+        approval_id: String,
+        market: AccountId,
+        request: AddToBundleRequest,
+    ) {
+        let bundle = self.internal_get_bundle(&request.bundle_id);
+        require(owner_id == bundle.owner_id, || {
+            ContractError::Unauthorized(format!("Only owner can add to bundle {}.", request.bundle_id))
+        });
+        require(bundle.items.len() < MAX_BUNDLE_SIZE, || {
+            ContractError::LimitExceeded(format!("Bundle can hold at most {} NFTs.", MAX_BUNDLE_SIZE))
+        });
+
+        let item_id = token_id.to_string() + ":" + &market;
+        require(!bundle.items.contains(&item_id), || {
+            ContractError::Conflict(format!("Bundle {} already holds {}.", request.bundle_id, item_id))
+        });
+
+        marketplace::nft_transfer(ValidAccountId::try_from(env::current_account_id()).unwrap(),
+                                  token_id,
+                                  Some(approval_id),
+                                  None,
+                                  &market,
+                                  1,
+                                  GAS_FOR_NFT_TRANSFER)
+            .then(ext_self::resolve_bundle_item(request.bundle_id,
+                                                item_id,
+                                                owner_id,
+                                                true,
+                                                &env::current_account_id(),
+                                                0,
+                                                GAS_FOR_RESOLVE_NFT_LOCK));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use near_sdk::json_types::U128;
+    use near_sdk::PromiseResult;
+
+    const LISTING_MSG: &str = r#"{"principal": "1000000000000000000000000", "apr_bps": 1000, "duration": 3600, "title": "Bundle"}"#;
+
+    /// Approves `nft_token_id` of `ALICE` for `bundle_id`, the transfer is
+    /// resolved by the caller.
+    fn approve_for_bundle(contract: &mut Contract, bundle_id: &TokenId, nft_token_id: &str) -> TokenId {
+        set_nft_context(ALICE, 0);
+        let msg = format!(r#"{{"bundle_id": "{}"}}"#, bundle_id);
+        contract.nft_on_approve(nft_token_id.to_string(), ALICE.to_string(), "0".to_string(), msg);
+        format!("{}:{}", nft_token_id, NFT_CONTRACT_ID)
+    }
+
+    fn resolve_added(contract: &mut Contract, bundle_id: &TokenId, item_id: &TokenId) -> bool {
+        set_callback_context(0, PromiseResult::Successful(vec![]));
+        contract.resolve_bundle_item(bundle_id.clone(), item_id.clone(), ALICE.to_string(), true)
+    }
+
+    fn setup_bundle(size: usize) -> (Contract, TokenId) {
+        let mut contract = setup_contract();
+        register(&mut contract, ALICE, NEAR);
+        register(&mut contract, BOB, NEAR);
+        set_context(ALICE, 0, 0);
+        let bundle_id = contract.create_bundle();
+        for index in 0..size {
+            let item_id = approve_for_bundle(&mut contract, &bundle_id, &index.to_string());
+            assert!(resolve_added(&mut contract, &bundle_id, &item_id));
+        }
+        (contract, bundle_id)
+    }
+
+    #[test]
+    fn nfts_arriving_at_a_full_bundle_are_returned() {
+        let (mut contract, bundle_id) = setup_bundle(0);
+        let item_ids: Vec<TokenId> = (0..=MAX_BUNDLE_SIZE)
+            .map(|index| approve_for_bundle(&mut contract, &bundle_id, &index.to_string()))
+            .collect();
+
+        let added: Vec<bool> = item_ids.iter().map(|item_id| resolve_added(&mut contract, &bundle_id, item_id)).collect();
+        assert_eq!(added.iter().filter(|added| **added).count(), MAX_BUNDLE_SIZE);
+        assert!(!added[MAX_BUNDLE_SIZE]);
+        assert!(!resolve_added(&mut contract, &bundle_id, &item_ids[0]));
+        assert_eq!(contract.get_bundle(bundle_id).unwrap().items.len(), MAX_BUNDLE_SIZE);
+    }
+
+    #[test]
+    fn repaid_bundle_is_released_once_every_nft_left() {
+        let (mut contract, bundle_id) = setup_bundle(2);
+        set_context(ALICE, 0, 0);
+        contract.list_bundle(bundle_id.clone(), ListingRequest::from_msg(LISTING_MSG, false));
+        fund_listing(&mut contract, BOB, &bundle_id, NEAR, 0);
+
+        set_context(ALICE, 0, 100);
+        let total_due = contract.get_repayment_quote(bundle_id.clone()).total_due;
+        set_context(ALICE, total_due.0, 100);
+        contract.repay_loan(bundle_id.clone());
+        set_callback_results(100, vec![PromiseResult::Successful(vec![]), PromiseResult::Failed]);
+        assert!(!contract.resolve_nft_return(bundle_id.clone(), LockedTokenState::Locked, total_due));
+
+        let token = contract.internal_get_token(&bundle_id);
+        assert_eq!(token.state, LockedTokenState::TransferToBorrower);
+        assert_eq!(token.bundle, vec![format!("1:{}", NFT_CONTRACT_ID)]);
+
+        set_context(CHARLIE, 0, 200);
+        contract.retry_collateral_release(bundle_id.clone());
+        set_callback_context(200, PromiseResult::Successful(vec![]));
+        assert!(contract.resolve_nft_return(bundle_id.clone(), LockedTokenState::TransferToBorrower, U128(0)));
+        assert!(contract.token_id_to_locked_token.get(&bundle_id).is_none());
+    }
+}
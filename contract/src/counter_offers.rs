@@ -0,0 +1,222 @@
+use crate::*;
+use near_sdk::json_types::U128;
+
+/// At most this many lenders can negotiate on one listing at a time.
+pub const MAX_COUNTER_OFFERS_PER_LISTING: usize = 20;
+
+/// Terms a lender proposes instead of the listed ones. The principal is
+/// escrowed until the counter-offer is accepted, superseded or refunded.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CounterOffer {
+    pub lender: AccountId,
+    pub principal: U128,
+    pub apr_bps: u64,
+    pub duration: u64,
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Escrows the attached deposit as the proposed principal. A lender's
+    /// previous counter-offer on the same listing is replaced and refunded,
+    /// otherwise the lender's storage for the credit is reserved.
+    #[payable]
+    pub fn make_counter_offer(&mut self, token_id: TokenId, apr_bps: u64, duration: Option<u64>) {
+        let lender = env::predecessor_account_id();
+        let principal = env::attached_deposit();
+        let token = self.internal_get_negotiable_token(&token_id);
+
+        require(lender != token.owner_id, || ContractError::Unauthorized("Owner can't counter own listing.".to_string()));
+        require(principal > 0, || ContractError::WrongDeposit("Attach the principal to lend.".to_string()));
+        validate_apr_bps(apr_bps, "Counter-offer");
+        let duration = duration.unwrap_or(token.duration);
+        require(duration > MIN_LISTING_DURATION_SEC, || {
+            ContractError::InvalidArgument(format!("Counter-offer duration must be longer than {} seconds.", MIN_LISTING_DURATION_SEC))
+        });
+        if !token.installments.is_empty() {
+            require(duration == token.duration, || {
+                ContractError::InvalidArgument(format!("Installment schedule of {} fixes the loan duration.", token_id))
+            });
+        }
+
+        let mut counter_offers = self.counter_offers.get(&token_id).unwrap_or_default();
+        if let Some(index) = counter_offers.iter().position(|offer| offer.lender == lender) {
+            let superseded = counter_offers.swap_remove(index);
+            Promise::new(superseded.lender).transfer(superseded.principal.0);
+        } else {
+            require(counter_offers.len() < MAX_COUNTER_OFFERS_PER_LISTING, || {
+                ContractError::LimitExceeded(format!("Listing already has {} counter-offers.", MAX_COUNTER_OFFERS_PER_LISTING))
+            });
+            self.internal_reserve_storage(&lender, CREDIT_STORAGE_BYTES);
+        }
+
+        ContractEvent::OfferCreated(OfferData::new(OfferKind::CounterOffer,
+                                                   &token_id,
+                                                   &lender,
+                                                   U128(principal),
+                                                   apr_bps,
+                                                   duration)).emit();
+
+        counter_offers.push(CounterOffer { lender, principal: U128(principal), apr_bps, duration });
+        self.counter_offers.insert(&token_id, &counter_offers);
+    }
+
+    pub fn withdraw_counter_offer(&mut self, token_id: TokenId) {
+        let lender = env::predecessor_account_id();
+        let offer = self.internal_take_counter_offer(&token_id, &lender);
+        self.internal_cancel_counter_offer(&token_id, offer);
+    }
+
+    pub fn reject_counter_offer(&mut self, token_id: TokenId, lender: ValidAccountId) {
+        let token = self.internal_get_negotiable_token(&token_id);
+        require(env::predecessor_account_id() == token.owner_id, || {
+            ContractError::Unauthorized("Only owner can reject a counter-offer.".to_string())
+        });
+
+        let offer = self.internal_take_counter_offer(&token_id, lender.as_ref());
+        self.internal_cancel_counter_offer(&token_id, offer);
+    }
+
+    /// Locks the listing on the counter-offer's terms, all other
+    /// counter-offers are refunded.
+    pub fn accept_counter_offer(&mut self, token_id: TokenId, lender: ValidAccountId) {
+        let mut token = self.internal_get_negotiable_token(&token_id);
+        require(env::predecessor_account_id() == token.owner_id, || {
+            ContractError::Unauthorized("Only owner can accept a counter-offer.".to_string())
+        });
+
+        let offer = self.internal_take_counter_offer(&token_id, lender.as_ref());
+        self.internal_release_storage(&offer.lender, CREDIT_STORAGE_BYTES);
+        token.duration = offer.duration;
+        self.internal_lock_token(token, &offer.lender, offer.principal.0, offer.apr_bps);
+    }
+
+    pub fn get_counter_offers(&self, token_id: TokenId) -> Vec<CounterOffer> {
+        self.counter_offers.get(&token_id).unwrap_or_default()
+    }
+}
+
+impl Contract {
+    fn internal_get_negotiable_token(&self, token_id: &TokenId) -> LockedToken {
+        let token = self.internal_get_token(token_id);
+
+        require_state(&token, LockedTokenState::Sale);
+        assert_token_currency(&token, &None);
+        require(!self.is_fractionalized(token_id), || {
+            ContractError::Unsupported(format!("Token {} is funded by selling shares.", token_id))
+        });
+        require(token.auction.is_none(), || {
+            ContractError::Unsupported(format!("Token {} is listed on auction, use place_bid.", token_id))
+        });
+        token
+    }
+
+    fn internal_take_counter_offer(&mut self, token_id: &TokenId, lender: &AccountId) -> CounterOffer {
+        let mut counter_offers = self.counter_offers.get(token_id).unwrap_or_default();
+        let index = counter_offers
+            .iter()
+            .position(|offer| &offer.lender == lender)
+            .unwrap_or_else(|| ContractError::NotFound(format!("counter-offer by {} on {}", lender, token_id)).panic());
+
+        let offer = counter_offers.swap_remove(index);
+        if counter_offers.is_empty() {
+            self.counter_offers.remove(token_id);
+        } else {
+            self.counter_offers.insert(token_id, &counter_offers);
+        }
+        offer
+    }
+
+    fn internal_cancel_counter_offer(&mut self, token_id: &TokenId, offer: CounterOffer) {
+        self.internal_release_storage(&offer.lender, CREDIT_STORAGE_BYTES);
+        ContractEvent::OfferCancelled(OfferData::new(OfferKind::CounterOffer,
+                                                     token_id,
+                                                     &offer.lender,
+                                                     offer.principal,
+                                                     offer.apr_bps,
+                                                     offer.duration)).emit();
+        Promise::new(offer.lender).transfer(offer.principal.0);
+    }
+
+    /// Refunds every counter-offer left on a listing that stopped being on sale.
+    pub(crate) fn internal_refund_counter_offers(&mut self, token_id: &TokenId) {
+        if let Some(counter_offers) = self.counter_offers.remove(token_id) {
+            for offer in counter_offers {
+                self.internal_release_storage(&offer.lender, CREDIT_STORAGE_BYTES);
+                Promise::new(offer.lender).transfer(offer.principal.0);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    const LISTING_MSG: &str = r#"{"principal": "1000000000000000000000000", "apr_bps": 1000, "duration": 3600, "title": "Listing"}"#;
+
+    fn setup_listing() -> (Contract, TokenId) {
+        let mut contract = setup_contract();
+        for account_id in &[ALICE, BOB, CHARLIE] {
+            register(&mut contract, account_id, NEAR);
+        }
+        let token_id = list_nft(&mut contract, ALICE, "1", LISTING_MSG);
+        (contract, token_id)
+    }
+
+    fn counter(contract: &mut Contract, token_id: &TokenId, lender_id: &str, principal: u128, apr_bps: u64) {
+        set_context(lender_id, principal, 10);
+        contract.make_counter_offer(token_id.clone(), apr_bps, None);
+    }
+
+    #[test]
+    fn accepted_counter_offer_sets_the_terms_and_refunds_the_others() {
+        let (mut contract, token_id) = setup_listing();
+        counter(&mut contract, &token_id, BOB, 8 * NEAR / 10, 900);
+        counter(&mut contract, &token_id, CHARLIE, 12 * NEAR / 10, 1_100);
+        counter(&mut contract, &token_id, BOB, 9 * NEAR / 10, 900);
+        assert_eq!(get_transfers(), vec![(BOB.to_string(), 8 * NEAR / 10)]);
+        assert_eq!(contract.get_counter_offers(token_id.clone()).len(), 2);
+
+        set_context(ALICE, 0, 20);
+        contract.accept_counter_offer(token_id.clone(), account(CHARLIE));
+        assert_eq!(get_transfers(), vec![(BOB.to_string(), 9 * NEAR / 10), (ALICE.to_string(), 12 * NEAR / 10)]);
+
+        let token = contract.internal_get_token(&token_id);
+        assert_eq!((token.creditor_id(), token.borrowed_money, token.apr_bps), (CHARLIE.to_string(), 12 * NEAR / 10, 1_100));
+        assert!(contract.get_counter_offers(token_id).is_empty());
+    }
+
+    #[test]
+    fn rejected_counter_offer_is_refunded() {
+        let (mut contract, token_id) = setup_listing();
+        counter(&mut contract, &token_id, BOB, 8 * NEAR / 10, 900);
+
+        set_context(ALICE, 0, 20);
+        contract.reject_counter_offer(token_id.clone(), account(BOB));
+        assert_eq!(get_transfers(), vec![(BOB.to_string(), 8 * NEAR / 10)]);
+        assert!(contract.get_counter_offers(token_id).is_empty());
+    }
+
+    #[test]
+    fn withdrawn_listing_refunds_its_counter_offers() {
+        let (mut contract, token_id) = setup_listing();
+        counter(&mut contract, &token_id, BOB, 8 * NEAR / 10, 900);
+        counter(&mut contract, &token_id, CHARLIE, 12 * NEAR / 10, 1_100);
+
+        set_context(ALICE, 1, 20);
+        contract.transfer_nft_back(token_id.clone());
+        assert_eq!(get_transfers(), vec![(BOB.to_string(), 8 * NEAR / 10), (CHARLIE.to_string(), 12 * NEAR / 10)]);
+        assert!(contract.get_counter_offers(token_id).is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "Unauthorized")]
+    fn only_the_owner_accepts_a_counter_offer() {
+        let (mut contract, token_id) = setup_listing();
+        counter(&mut contract, &token_id, BOB, 8 * NEAR / 10, 900);
+        set_context(CHARLIE, 0, 20);
+        contract.accept_counter_offer(token_id, account(BOB));
+    }
+}
@@ -1,7 +1,23 @@
-use near_sdk::json_types::ValidAccountId;
-use near_sdk::{ext_contract};
+use near_sdk::json_types::{ValidAccountId, U128};
+use near_sdk::{env, ext_contract, Gas, PromiseResult};
 use near_contract_standards::non_fungible_token::{TokenId};
 
+use crate::errors::{require, ContractError};
+use crate::listing_request::ListingRequest;
+use crate::lender_offers::{AcceptLenderOfferRequest, LenderOffer};
+use crate::locked_token::LockedTokenState;
+use crate::pools::{PoolAsset, PoolLoanRequest};
+
+pub const GAS_FOR_NFT_TRANSFER: Gas = 20_000_000_000_000;
+/// Leaves room to send the NFT back when the listing can't be recorded.
+pub const GAS_FOR_RESOLVE_NFT_LOCK: Gas = 40_000_000_000_000;
+pub const GAS_FOR_RESOLVE_NFT_RETURN: Gas = 25_000_000_000_000;
+pub const GAS_FOR_FT_TRANSFER: Gas = 10_000_000_000_000;
+pub const GAS_FOR_RESOLVE_FT_PAYOUT: Gas = 10_000_000_000_000;
+pub const GAS_FOR_SHARE_TOKEN_INIT: Gas = 30_000_000_000_000;
+pub const GAS_FOR_RESOLVE_SHARE_TOKEN: Gas = 10_000_000_000_000;
+pub const GAS_FOR_RESOLVE_SHARE_PURCHASE: Gas = 60_000_000_000_000;
+
 #[ext_contract(marketplace)]
 pub trait TokenTransfer {
     fn nft_transfer(
@@ -11,4 +27,111 @@ pub trait TokenTransfer {
         approval_id: Option<String>,
         memo: Option<String>
     );
-}
\ No newline at end of file
+}
+
+#[ext_contract(fungible_token)]
+pub trait FungibleTokenTransfer {
+    fn ft_transfer(
+        &mut self,
+        receiver_id: ValidAccountId,
+        amount: U128,
+        memo: Option<String>
+    );
+}
+
+#[ext_contract(ext_self)]
+pub trait SelfCallbacks {
+    fn resolve_nft_lock(
+        &mut self,
+        token_id: TokenId,
+        owner_id: AccountId,
+        listing: ListingRequest
+    ) -> bool;
+
+    fn resolve_lender_offer_lock(
+        &mut self,
+        token_id: TokenId,
+        owner_id: AccountId,
+        offer: LenderOffer,
+        request: AcceptLenderOfferRequest
+    ) -> bool;
+
+    fn resolve_nft_return(
+        &mut self,
+        token_id: TokenId,
+        previous_state: LockedTokenState,
+        repayment: U128
+    ) -> bool;
+
+    fn resolve_bundle_item(
+        &mut self,
+        bundle_id: TokenId,
+        item_id: TokenId,
+        owner_id: AccountId,
+        added: bool
+    ) -> bool;
+
+    fn resolve_share_token_deploy(
+        &mut self,
+        share_token: AccountId,
+        owner_id: AccountId,
+        deposit: U128
+    ) -> bool;
+
+    fn resolve_share_purchase(
+        &mut self,
+        share_token: AccountId,
+        buyer: AccountId,
+        shares: U128
+    ) -> bool;
+
+    fn resolve_defaulted_collateral(
+        &mut self,
+        share_token: AccountId,
+        receiver_id: AccountId,
+        price: U128
+    ) -> bool;
+
+    fn resolve_pool_loan(
+        &mut self,
+        token_id: TokenId,
+        owner_id: AccountId,
+        market: AccountId,
+        request: PoolLoanRequest
+    ) -> bool;
+
+    fn resolve_pool_asset_sale(
+        &mut self,
+        asset: PoolAsset,
+        buyer: AccountId
+    ) -> bool;
+
+    fn resolve_liquidation(
+        &mut self,
+        token_id: TokenId,
+        winner: AccountId,
+        price: U128,
+        debt: U128
+    ) -> bool;
+
+    fn resolve_ft_payout(
+        &mut self,
+        currency: AccountId,
+        receiver_id: AccountId,
+        amount: U128
+    ) -> bool;
+}
+
+/// Whether the single promise this callback depends on succeeded.
+pub fn is_promise_success() -> bool {
+    require(env::promise_results_count() == 1, || {
+        ContractError::Internal("Expected exactly one promise result.".to_string())
+    });
+    matches!(env::promise_result(0), PromiseResult::Successful(_))
+}
+
+/// Whether the `index`-th of the joint promises this callback depends on
+/// succeeded.
+pub fn is_promise_success_at(index: u64) -> bool {
+    matches!(env::promise_result(index), PromiseResult::Successful(_))
+}
@@ -0,0 +1,252 @@
+use crate::*;
+use near_sdk::serde_json;
+use near_sdk::PromiseOrValue;
+
+/// `ft_transfer_call` msg telling what the transferred tokens are for.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum FtTransferRequest {
+    /// Funds a `Sale` listing, the amount must equal its principal.
+    FundLoan { token_id: TokenId },
+    /// Repays a `Locked` loan, partially if the amount is below `total_due`.
+    RepayLoan { token_id: TokenId },
+    /// Sent by a share token holder to get refunded while the loan is funded
+    /// or to redeem the shares for proceeds once it's settled.
+    RedeemShares,
+    /// Sent by a share token holder to lock the shares as a vote on the
+    /// collateral of a defaulted loan.
+    VoteOnDefault { decision: DefaultDecision },
+}
+
+pub fn currency_name(currency: &Option<AccountId>) -> &str {
+    currency.as_deref().unwrap_or("NEAR")
+}
+
+pub fn assert_token_currency(token: &LockedToken, currency: &Option<AccountId>) {
+    if &token.currency != currency {
+        ContractError::Unsupported(format!("Loan {} is denominated in {}, not {}.",
+                                           token.token_id,
+                                           currency_name(&token.currency),
+                                           currency_name(currency))).panic();
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    pub fn add_currency(&mut self, currency: ValidAccountId) {
+        self.assert_owner();
+        self.accepted_currencies.insert(currency.as_ref());
+    }
+
+    /// Loans already denominated in `currency` can still be repaid.
+    pub fn remove_currency(&mut self, currency: ValidAccountId) {
+        self.assert_owner();
+        self.accepted_currencies.remove(currency.as_ref());
+    }
+
+    pub fn get_currencies(&self) -> Vec<AccountId> {
+        self.accepted_currencies.to_vec()
+    }
+
+    /// NEP-141 receiver for loan currencies and share tokens. Unused tokens
+    /// are returned to the sender by the FT contract.
+    pub fn ft_on_transfer(&mut self, sender_id: ValidAccountId, amount: U128, msg: String) -> PromiseOrValue<U128> {
+        let currency = env::predecessor_account_id();
+        let sender_id: AccountId = sender_id.into();
+
+        let request: FtTransferRequest = serde_json::from_str(&msg)
+            .unwrap_or_else(|e| ContractError::InvalidMsg(format!("Invalid ft_transfer_call msg: {}", e)).panic());
+
+        let unused = match request {
+            FtTransferRequest::FundLoan { token_id } => {
+                self.assert_accepted_currency(&currency);
+                self.internal_fund_listing(&sender_id, token_id, amount.0, Some(currency));
+                0
+            }
+            FtTransferRequest::RepayLoan { token_id } => {
+                self.internal_repay_loan_partially(&sender_id, token_id, amount.0, Some(currency))
+            }
+            FtTransferRequest::RedeemShares => {
+                self.internal_redeem_shares(&currency, &sender_id, amount.0);
+                0
+            }
+            FtTransferRequest::VoteOnDefault { decision } => {
+                self.internal_vote_on_default(&currency, &sender_id, amount.0, decision);
+                0
+            }
+        };
+        PromiseOrValue::Value(U128(unused))
+    }
+
+    /// Credits a failed payout to the receiver, to be claimed with
+    /// `withdraw_ft_payout`.
+    #[private]
+    pub fn resolve_ft_payout(&mut self, currency: AccountId, receiver_id: AccountId, amount: U128) -> bool {
+        if is_promise_success() {
+            return true;
+        }
+
+        env::log(format!("Failed to pay {} {} to {}, kept for withdrawal.", amount.0, currency, receiver_id).as_bytes());
+        let key = (currency, receiver_id);
+        let pending = self.pending_ft_payouts.get(&key).map_or(0, |pending| pending.0);
+        self.pending_ft_payouts.insert(&key, &U128(pending + amount.0));
+        false
+    }
+
+    pub fn withdraw_ft_payout(&mut self, currency: ValidAccountId) {
+        let key = (currency.into(), env::predecessor_account_id());
+        let amount = self
+            .pending_ft_payouts
+            .remove(&key)
+            .unwrap_or_else(|| ContractError::NotFound(format!("pending {} payout", key.0)).panic());
+
+        let (currency, receiver_id) = key;
+        self.internal_pay(&Some(currency), &receiver_id, amount.0);
+    }
+
+    pub fn get_pending_ft_payout(&self, currency: AccountId, account_id: AccountId) -> U128 {
+        self.pending_ft_payouts.get(&(currency, account_id)).unwrap_or(U128(0))
+    }
+}
+
+impl Contract {
+    pub(crate) fn assert_accepted_currency(&self, currency: &AccountId) {
+        if !self.accepted_currencies.contains(currency) {
+            ContractError::Unsupported(format!("Currency {} isn't accepted.", currency)).panic();
+        }
+    }
+
+    /// Pays `amount` of `currency` (`None` for NEAR) out of the contract.
+    pub(crate) fn internal_pay(&self, currency: &Option<AccountId>, receiver_id: &AccountId, amount: u128) {
+        if amount == 0 {
+            return;
+        }
+        match currency {
+            None => {
+                Promise::new(receiver_id.clone()).transfer(amount);
+            }
+            Some(currency) => {
+                fungible_token::ft_transfer(ValidAccountId::try_from(receiver_id.clone()).unwrap(),
+                                            U128(amount),
+                                            None,
+                                            currency,
+                                            1,
+                                            GAS_FOR_FT_TRANSFER)
+                    .then(ext_self::resolve_ft_payout(currency.clone(),
+                                                      receiver_id.clone(),
+                                                      U128(amount),
+                                                      &env::current_account_id(),
+                                                      0,
+                                                      GAS_FOR_RESOLVE_FT_PAYOUT));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use near_sdk::PromiseResult;
+
+    const USDC: &str = "usdc.near";
+    const PRINCIPAL: u128 = 1_000_000_000;
+    const LISTING_MSG: &str = r#"{"principal": "1000000000", "apr_bps": 1000, "duration": 3600, "title": "Listing",
+        "interest_model": "FlatFee", "currency": "usdc.near"}"#;
+
+    fn setup_usdc_listing() -> (Contract, TokenId) {
+        let mut contract = setup_contract();
+        register(&mut contract, ALICE, NEAR);
+        register(&mut contract, BOB, NEAR);
+        set_context(OWNER, 0, 0);
+        contract.add_currency(account(USDC));
+        let token_id = list_nft(&mut contract, ALICE, "1", LISTING_MSG);
+        (contract, token_id)
+    }
+
+    /// `ft_transfer_call` of `amount` USDC by `sender_id`, returns the unused
+    /// amount.
+    fn transfer_usdc(contract: &mut Contract, sender_id: &str, amount: u128, msg: String, timestamp_sec: u64) -> u128 {
+        set_context(USDC, 0, timestamp_sec);
+        match contract.ft_on_transfer(account(sender_id), U128(amount), msg) {
+            PromiseOrValue::Value(unused) => unused.0,
+            PromiseOrValue::Promise(_) => panic!("ft_on_transfer returned a promise"),
+        }
+    }
+
+    fn assert_usdc_payout(receiver_id: &str, amount: u128) {
+        let payout = get_function_calls()
+            .into_iter()
+            .find(|(_, method_name, _)| method_name == "ft_transfer")
+            .unwrap();
+        assert_eq!((payout.0.as_str(), payout.2["receiver_id"].as_str(), payout.2["amount"].as_str()),
+                   (USDC, Some(receiver_id), Some(amount.to_string().as_str())));
+    }
+
+    fn fund(contract: &mut Contract, token_id: &TokenId) {
+        let msg = format!(r#"{{"action": "fund_loan", "token_id": "{}"}}"#, token_id);
+        assert_eq!(transfer_usdc(contract, BOB, PRINCIPAL, msg, 0), 0);
+    }
+
+    fn repay(contract: &mut Contract, token_id: &TokenId, amount: u128) -> u128 {
+        let msg = format!(r#"{{"action": "repay_loan", "token_id": "{}"}}"#, token_id);
+        transfer_usdc(contract, ALICE, amount, msg, 100)
+    }
+
+    #[test]
+    fn usdc_loan_is_funded_and_repaid_in_usdc() {
+        let (mut contract, token_id) = setup_usdc_listing();
+        fund(&mut contract, &token_id);
+        assert_usdc_payout(ALICE, PRINCIPAL);
+        assert_eq!(contract.internal_get_token(&token_id).creditor_id(), BOB);
+
+        assert_eq!(repay(&mut contract, &token_id, PRINCIPAL * 12 / 10), PRINCIPAL / 10);
+        assert_eq!(contract.internal_get_token(&token_id).state, LockedTokenState::TransferToBorrower);
+
+        set_callback_context(100, PromiseResult::Successful(vec![]));
+        assert!(contract.resolve_nft_return(token_id.clone(), LockedTokenState::Locked, U128(PRINCIPAL * 11 / 10)));
+        assert_usdc_payout(BOB, PRINCIPAL * 11 / 10);
+    }
+
+    #[test]
+    fn partial_usdc_repayment_is_forwarded_to_the_creditor() {
+        let (mut contract, token_id) = setup_usdc_listing();
+        fund(&mut contract, &token_id);
+
+        assert_eq!(repay(&mut contract, &token_id, PRINCIPAL / 2), 0);
+        assert_usdc_payout(BOB, PRINCIPAL / 2);
+        assert_eq!(contract.internal_get_token(&token_id).outstanding_principal.0, PRINCIPAL * 6 / 10);
+    }
+
+    #[test]
+    fn failed_payout_is_kept_for_withdrawal() {
+        let (mut contract, _) = setup_usdc_listing();
+        set_callback_context(0, PromiseResult::Failed);
+        assert!(!contract.resolve_ft_payout(USDC.to_string(), BOB.to_string(), U128(PRINCIPAL)));
+        assert_eq!(contract.get_pending_ft_payout(USDC.to_string(), BOB.to_string()).0, PRINCIPAL);
+
+        set_context(BOB, 0, 10);
+        contract.withdraw_ft_payout(account(USDC));
+        assert_usdc_payout(BOB, PRINCIPAL);
+        assert_eq!(contract.get_pending_ft_payout(USDC.to_string(), BOB.to_string()).0, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Unsupported")]
+    fn usdc_loan_cant_be_repaid_in_near() {
+        let (mut contract, token_id) = setup_usdc_listing();
+        fund(&mut contract, &token_id);
+        set_context(ALICE, NEAR, 100);
+        contract.repay_loan(token_id);
+    }
+
+    #[test]
+    #[should_panic(expected = "Unsupported")]
+    fn listings_arent_funded_in_other_currencies() {
+        let (mut contract, token_id) = setup_usdc_listing();
+        set_context("usdt.near", 0, 0);
+        let msg = format!(r#"{{"action": "fund_loan", "token_id": "{}"}}"#, token_id);
+        contract.ft_on_transfer(account(BOB), U128(PRINCIPAL), msg);
+    }
+}
@@ -0,0 +1,201 @@
+use crate::*;
+
+/// Every way a contract call can fail. The panic message starts with the
+/// stable `E<code> <Name>:` prefix, e.g.
+/// `E100 TokenNotFound: Can't find token with Id: 1:nft.near in contract.`
+#[derive(Debug)]
+pub enum ContractError {
+    TokenNotFound(TokenId),
+    /// Any other missing record, e.g. "lender offer 3".
+    NotFound(String),
+    NoCreditor(TokenId),
+    WrongState { token_id: TokenId, expected: LockedTokenState, actual: LockedTokenState },
+    /// The record already exists or is already in the requested condition.
+    Conflict(String),
+    NotOverdue(TokenId),
+    Overdue(TokenId),
+    /// The call doesn't apply to this kind of listing or currency.
+    Unsupported(String),
+    /// The auction, liquidation or vote isn't at the stage the call needs.
+    WrongPhase(String),
+    Unauthorized(String),
+    WrongDeposit(String),
+    NotRegistered(AccountId),
+    InsufficientStorage { account_id: AccountId, required: u128 },
+    InsufficientFunds(String),
+    InvalidArgument(String),
+    InvalidMsg(String),
+    LimitExceeded(String),
+    /// Storage doesn't match the contract's invariants.
+    Internal(String),
+}
+
+impl ContractError {
+    pub fn code(&self) -> u32 {
+        match self {
+            ContractError::TokenNotFound(_) => 100,
+            ContractError::NotFound(_) => 101,
+            ContractError::NoCreditor(_) => 102,
+            ContractError::WrongState { .. } => 200,
+            ContractError::Conflict(_) => 201,
+            ContractError::NotOverdue(_) => 202,
+            ContractError::Overdue(_) => 203,
+            ContractError::Unsupported(_) => 204,
+            ContractError::WrongPhase(_) => 205,
+            ContractError::Unauthorized(_) => 300,
+            ContractError::WrongDeposit(_) => 400,
+            ContractError::NotRegistered(_) => 401,
+            ContractError::InsufficientStorage { .. } => 402,
+            ContractError::InsufficientFunds(_) => 403,
+            ContractError::InvalidArgument(_) => 500,
+            ContractError::InvalidMsg(_) => 501,
+            ContractError::LimitExceeded(_) => 502,
+            ContractError::Internal(_) => 900,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ContractError::TokenNotFound(_) => "TokenNotFound",
+            ContractError::NotFound(_) => "NotFound",
+            ContractError::NoCreditor(_) => "NoCreditor",
+            ContractError::WrongState { .. } => "WrongState",
+            ContractError::Conflict(_) => "Conflict",
+            ContractError::NotOverdue(_) => "NotOverdue",
+            ContractError::Overdue(_) => "Overdue",
+            ContractError::Unsupported(_) => "Unsupported",
+            ContractError::WrongPhase(_) => "WrongPhase",
+            ContractError::Unauthorized(_) => "Unauthorized",
+            ContractError::WrongDeposit(_) => "WrongDeposit",
+            ContractError::NotRegistered(_) => "NotRegistered",
+            ContractError::InsufficientStorage { .. } => "InsufficientStorage",
+            ContractError::InsufficientFunds(_) => "InsufficientFunds",
+            ContractError::InvalidArgument(_) => "InvalidArgument",
+            ContractError::InvalidMsg(_) => "InvalidMsg",
+            ContractError::LimitExceeded(_) => "LimitExceeded",
+            ContractError::Internal(_) => "Internal",
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            ContractError::TokenNotFound(token_id) => format!("Can't find token with Id: {} in contract.", token_id),
+            ContractError::NotFound(record) => format!("Can't find {}.", record),
+            ContractError::NoCreditor(token_id) => format!("Token {} has no creditor.", token_id),
+            ContractError::WrongState { token_id, expected, actual } => {
+                format!("Token {} is in state {}, expected {}.", token_id, actual, expected)
+            }
+            ContractError::NotOverdue(token_id) => {
+                format!("There is still time for borrower to return money for {}.", token_id)
+            }
+            ContractError::Overdue(token_id) => format!("Loan {} is overdue.", token_id),
+            ContractError::NotRegistered(account_id) => {
+                format!("Account {} isn't registered, call storage_deposit.", account_id)
+            }
+            ContractError::InsufficientStorage { account_id, required } => {
+                format!("Account {} needs {} more storage balance, call storage_deposit.", account_id, required)
+            }
+            ContractError::Conflict(message)
+            | ContractError::Unsupported(message)
+            | ContractError::WrongPhase(message)
+            | ContractError::Unauthorized(message)
+            | ContractError::WrongDeposit(message)
+            | ContractError::InsufficientFunds(message)
+            | ContractError::InvalidArgument(message)
+            | ContractError::InvalidMsg(message)
+            | ContractError::LimitExceeded(message)
+            | ContractError::Internal(message) => message.clone(),
+        }
+    }
+
+    /// The only place the contract panics from.
+    pub fn panic(&self) -> ! {
+        env::panic(format!("E{} {}: {}", self.code(), self.name(), self.message()).as_bytes())
+    }
+}
+
+/// Panics with the error built by `error` unless `condition` holds.
+pub fn require(condition: bool, error: impl FnOnce() -> ContractError) {
+    if !condition {
+        error().panic();
+    }
+}
+
+/// Panics with `WrongState` unless `token` is in `expected`.
+pub fn require_state(token: &LockedToken, expected: LockedTokenState) {
+    if token.state != expected {
+        ContractError::WrongState {
+            token_id: token.token_id.clone(),
+            expected,
+            actual: token.state.clone(),
+        }.panic();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use std::collections::HashSet;
+
+    const LISTING_MSG: &str = r#"{"principal": "1000000000000000000000000", "apr_bps": 1000, "duration": 3600, "title": "Listing"}"#;
+
+    fn all_errors() -> Vec<ContractError> {
+        let token_id = "1:nft.near".to_string();
+        let message = "message".to_string();
+        vec![
+            ContractError::TokenNotFound(token_id.clone()),
+            ContractError::NotFound(message.clone()),
+            ContractError::NoCreditor(token_id.clone()),
+            ContractError::WrongState {
+                token_id: token_id.clone(),
+                expected: LockedTokenState::Sale,
+                actual: LockedTokenState::Locked,
+            },
+            ContractError::Conflict(message.clone()),
+            ContractError::NotOverdue(token_id.clone()),
+            ContractError::Overdue(token_id),
+            ContractError::Unsupported(message.clone()),
+            ContractError::WrongPhase(message.clone()),
+            ContractError::Unauthorized(message.clone()),
+            ContractError::WrongDeposit(message.clone()),
+            ContractError::NotRegistered(ALICE.to_string()),
+            ContractError::InsufficientStorage { account_id: ALICE.to_string(), required: 1 },
+            ContractError::InsufficientFunds(message.clone()),
+            ContractError::InvalidArgument(message.clone()),
+            ContractError::InvalidMsg(message.clone()),
+            ContractError::LimitExceeded(message.clone()),
+            ContractError::Internal(message),
+        ]
+    }
+
+    #[test]
+    fn codes_are_unique_and_names_match_variants() {
+        let errors = all_errors();
+        let codes: HashSet<u32> = errors.iter().map(|error| error.code()).collect();
+        assert_eq!(codes.len(), errors.len());
+        for error in &errors {
+            assert!(format!("{:?}", error).starts_with(error.name()));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "E100 TokenNotFound: Can't find token with Id: 1:nft.near in contract.")]
+    fn panic_message_starts_with_code_and_name() {
+        let contract = setup_contract();
+        contract.internal_get_token(&"1:nft.near".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "E200 WrongState: Token 1:nft.near is in state Locked, expected Sale.")]
+    fn wrong_state_names_both_states() {
+        let mut contract = setup_contract();
+        register(&mut contract, ALICE, NEAR);
+        register(&mut contract, BOB, NEAR);
+        let token_id = list_nft(&mut contract, ALICE, "1", LISTING_MSG);
+        fund_listing(&mut contract, BOB, &token_id, NEAR, 0);
+
+        set_context(ALICE, 0, 10);
+        contract.transfer_nft_back(token_id);
+    }
+}
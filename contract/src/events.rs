@@ -0,0 +1,317 @@
+use crate::*;
+use near_sdk::json_types::{U128, U64};
+use near_sdk::serde_json;
+
+/// NEP-297 `standard` of the events logged by the contract.
+pub const EVENT_STANDARD: &str = "alevoro";
+pub const EVENT_STANDARD_VERSION: &str = "1.0.0";
+
+/// Lifecycle event, logged as `EVENT_JSON:{"standard":"alevoro","version":"1.0.0","event":...,"data":...}`.
+#[derive(Serialize, Debug)]
+#[serde(crate = "near_sdk::serde", tag = "event", content = "data", rename_all = "snake_case")]
+pub enum ContractEvent {
+    ListingCreated(ListingData),
+    ListingCancelled(TokenData),
+    LoanFunded(LoanData),
+    LoanRefinanced(RefinanceData),
+    LoanRepaid(RepaymentData),
+    LoanExtended(ExtensionData),
+    LoanDefaulted(TokenData),
+    LiquidationStarted(LiquidationData),
+    LiquidationBid(LiquidationBidData),
+    CollateralReleased(ReleaseData),
+    OfferCreated(OfferData),
+    OfferCancelled(OfferData),
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct EventLog<'a> {
+    standard: &'static str,
+    version: &'static str,
+    #[serde(flatten)]
+    event: &'a ContractEvent,
+}
+
+impl ContractEvent {
+    pub fn emit(&self) {
+        let log = EventLog { standard: EVENT_STANDARD, version: EVENT_STANDARD_VERSION, event: self };
+        env::log(format!("EVENT_JSON:{}", serde_json::to_string(&log).unwrap()).as_bytes());
+    }
+}
+
+/// Fields shared by every event about a listing or loan. `market` is unset for
+/// bundles, `timestamp` is the block timestamp in nanoseconds.
+#[derive(Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokenData {
+    pub token_id: TokenId,
+    pub market: Option<AccountId>,
+    pub owner_id: AccountId,
+    pub creditor_id: Option<AccountId>,
+    pub timestamp: U64,
+}
+
+impl TokenData {
+    pub fn new(token_id: &str, owner_id: &str, creditor_id: Option<AccountId>) -> Self {
+        Self {
+            token_id: token_id.to_string(),
+            market: token_market(token_id),
+            owner_id: owner_id.to_string(),
+            creditor_id,
+            timestamp: U64(env::block_timestamp()),
+        }
+    }
+
+    pub fn from_token(token: &LockedToken) -> Self {
+        Self::new(&token.token_id, &token.owner_id, token.creditor.clone())
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ListingData {
+    #[serde(flatten)]
+    pub token: TokenData,
+    pub principal: U128,
+    pub apr_bps: u64,
+    pub duration: u64,
+    pub currency: Option<AccountId>,
+    pub auction: bool,
+}
+
+impl ListingData {
+    pub fn from_token(token: &LockedToken) -> Self {
+        Self {
+            token: TokenData::from_token(token),
+            principal: token.outstanding_principal,
+            apr_bps: token.apr_bps,
+            duration: token.duration,
+            currency: token.currency.clone(),
+            auction: token.auction.is_some(),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct LoanData {
+    #[serde(flatten)]
+    pub token: TokenData,
+    pub principal: U128,
+    pub apr_bps: u64,
+    pub duration: u64,
+    pub currency: Option<AccountId>,
+}
+
+impl LoanData {
+    pub fn from_token(token: &LockedToken) -> Self {
+        Self {
+            token: TokenData::from_token(token),
+            principal: token.outstanding_principal,
+            apr_bps: token.apr_bps,
+            duration: token.duration,
+            currency: token.currency.clone(),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RefinanceData {
+    #[serde(flatten)]
+    pub loan: LoanData,
+    pub previous_creditor_id: AccountId,
+    pub payoff: U128,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RepaymentData {
+    #[serde(flatten)]
+    pub token: TokenData,
+    pub amount: U128,
+    pub outstanding_principal: U128,
+    pub currency: Option<AccountId>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ExtensionData {
+    #[serde(flatten)]
+    pub token: TokenData,
+    pub duration: u64,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct LiquidationData {
+    #[serde(flatten)]
+    pub token: TokenData,
+    pub debt: U128,
+    pub deadline: U64,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct LiquidationBidData {
+    #[serde(flatten)]
+    pub token: TokenData,
+    pub bidder_id: AccountId,
+    pub amount: U128,
+}
+
+/// The collateral left the contract to `receiver_id`, `price` is set when it
+/// was sold.
+#[derive(Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ReleaseData {
+    #[serde(flatten)]
+    pub token: TokenData,
+    pub receiver_id: AccountId,
+    pub price: Option<U128>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(crate = "near_sdk::serde", rename_all = "snake_case")]
+pub enum OfferKind {
+    Bid,
+    CounterOffer,
+    LenderOffer,
+    RefinanceOffer,
+}
+
+/// `account_id` made the offer, the borrower for a refinance offer and the
+/// lender otherwise. Lender offers for any token of a market have no
+/// `token_id`.
+#[derive(Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct OfferData {
+    pub kind: OfferKind,
+    pub offer_id: Option<u64>,
+    pub token_id: Option<TokenId>,
+    pub market: Option<AccountId>,
+    pub account_id: AccountId,
+    pub principal: U128,
+    pub apr_bps: u64,
+    pub duration: u64,
+    pub timestamp: U64,
+}
+
+impl OfferData {
+    pub fn new(kind: OfferKind, token_id: &str, account_id: &str, principal: U128, apr_bps: u64, duration: u64) -> Self {
+        let token = TokenData::new(token_id, account_id, None);
+        Self {
+            kind,
+            offer_id: None,
+            token_id: Some(token.token_id),
+            market: token.market,
+            account_id: token.owner_id,
+            principal,
+            apr_bps,
+            duration,
+            timestamp: token.timestamp,
+        }
+    }
+
+    pub fn from_lender_offer(offer: &LenderOffer) -> Self {
+        Self {
+            kind: OfferKind::LenderOffer,
+            offer_id: Some(offer.id),
+            token_id: offer.token_id.clone(),
+            market: Some(offer.market.clone()),
+            account_id: offer.lender.clone(),
+            principal: offer.principal,
+            apr_bps: offer.apr_bps,
+            duration: offer.duration,
+            timestamp: U64(env::block_timestamp()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use near_sdk::serde_json::json;
+    use near_sdk::PromiseResult;
+
+    const LISTING_MSG: &str = r#"{"principal": "1000000000000000000000000", "apr_bps": 1000, "duration": 3600, "title": "Listing",
+        "interest_model": "FlatFee"}"#;
+    const TOKEN_ID: &str = "1:nft.near";
+
+    fn setup_listing() -> Contract {
+        let mut contract = setup_contract();
+        register(&mut contract, ALICE, NEAR);
+        register(&mut contract, BOB, NEAR);
+        list_nft(&mut contract, ALICE, "1", LISTING_MSG);
+        contract
+    }
+
+    #[test]
+    fn listing_is_logged_with_its_terms() {
+        setup_listing();
+        assert_eq!(get_events(), vec![json!({
+            "standard": "alevoro",
+            "version": "1.0.0",
+            "event": "listing_created",
+            "data": {
+                "token_id": TOKEN_ID,
+                "market": NFT_CONTRACT_ID,
+                "owner_id": ALICE,
+                "creditor_id": null,
+                "timestamp": "0",
+                "principal": "1000000000000000000000000",
+                "apr_bps": 1000,
+                "duration": 3600,
+                "currency": null,
+                "auction": false,
+            },
+        })]);
+    }
+
+    #[test]
+    fn loan_events_follow_its_lifecycle() {
+        let mut contract = setup_listing();
+        fund_listing(&mut contract, BOB, &TOKEN_ID.to_string(), NEAR, 10);
+        let events = get_events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0]["event"], "loan_funded");
+        assert_eq!(events[0]["data"]["creditor_id"], BOB);
+        assert_eq!(events[0]["data"]["timestamp"], "10000000000");
+
+        set_context(ALICE, NEAR * 11 / 10, 100);
+        contract.repay_loan(TOKEN_ID.to_string());
+        set_callback_context(100, PromiseResult::Successful(vec![]));
+        contract.resolve_nft_return(TOKEN_ID.to_string(), LockedTokenState::Locked, U128(NEAR * 11 / 10));
+        let events = get_events();
+        assert_eq!(events.iter().map(|event| event["event"].as_str().unwrap()).collect::<Vec<_>>(),
+                   vec!["loan_repaid", "collateral_released"]);
+        assert_eq!((&events[0]["data"]["amount"], &events[0]["data"]["outstanding_principal"]),
+                   (&json!("1100000000000000000000000"), &json!("0")));
+        assert_eq!((&events[1]["data"]["receiver_id"], &events[1]["data"]["price"]), (&json!(ALICE), &json!(null)));
+    }
+
+    #[test]
+    fn offers_are_logged_with_their_maker() {
+        let mut contract = setup_listing();
+        set_context(BOB, NEAR, 10);
+        contract.make_counter_offer(TOKEN_ID.to_string(), 500, Some(7200));
+        assert_eq!(get_events(), vec![json!({
+            "standard": "alevoro",
+            "version": "1.0.0",
+            "event": "offer_created",
+            "data": {
+                "kind": "counter_offer",
+                "offer_id": null,
+                "token_id": TOKEN_ID,
+                "market": NFT_CONTRACT_ID,
+                "account_id": BOB,
+                "principal": "1000000000000000000000000",
+                "apr_bps": 500,
+                "duration": 7200,
+                "timestamp": "10000000000",
+            },
+        })]);
+    }
+}
@@ -0,0 +1,260 @@
+use crate::*;
+use near_sdk::json_types::{U128, U64};
+
+/// Pending proposal to extend a `Locked` loan, waiting for the other party.
+///
+/// The borrower pays `fee` to the creditor, plus the interest accrued so far
+/// unless `capitalize_interest` adds it to the outstanding principal instead.
+/// Installments are shares of the original principal, so loans with
+/// installments can't capitalize interest.
+/// Whatever the borrower attached when proposing is held in `escrow`.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct LoanExtension {
+    pub proposer: AccountId,
+    /// New loan duration in seconds, counted from the original start time.
+    pub new_duration: U64,
+    pub fee: U128,
+    pub capitalize_interest: bool,
+    pub escrow: U128,
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Proposes new terms, replacing a previous proposal. A proposing borrower
+    /// attaches the fee and, unless capitalized, the accrued interest.
+    #[payable]
+    pub fn propose_loan_extension(&mut self, token_id: TokenId, new_duration: u64, fee: U128, capitalize_interest: bool) {
+        let proposer = env::predecessor_account_id();
+        let deposit = env::attached_deposit();
+        let token = self.internal_get_extendable_token(&token_id);
+
+        require(new_duration > token.duration, || {
+            ContractError::InvalidArgument(format!("Extension must increase the loan duration {}.", token.duration))
+        });
+        require(!capitalize_interest || token.installments.is_empty(), || {
+            ContractError::Unsupported("Interest of loans with installments can't be capitalized.".to_string())
+        });
+
+        if proposer == token.owner_id {
+            let required = self.internal_extension_payment(&token, fee.0, capitalize_interest);
+            require(deposit >= required, || {
+                ContractError::WrongDeposit(format!("Attached deposit {} doesn't cover the extension payment {}.", deposit, required))
+            });
+        } else if Some(&proposer) == token.creditor.as_ref() {
+            require(deposit == 0, || ContractError::WrongDeposit("Creditor doesn't pay for an extension.".to_string()));
+        } else {
+            ContractError::Unauthorized("Only borrower or creditor can propose an extension.".to_string()).panic();
+        }
+
+        self.internal_drop_loan_extension(&token_id);
+        let initial_storage_usage = env::storage_usage();
+        self.loan_extensions.insert(&token_id, &LoanExtension {
+            proposer: proposer.clone(),
+            new_duration: U64(new_duration),
+            fee,
+            capitalize_interest,
+            escrow: U128(deposit),
+        });
+        self.internal_charge_storage(&proposer, initial_storage_usage);
+    }
+
+    /// Accepts the other party's proposal. An accepting borrower attaches the
+    /// fee and, unless capitalized, the accrued interest. Excess is refunded.
+    #[payable]
+    pub fn accept_loan_extension(&mut self, token_id: TokenId) {
+        let account_id = env::predecessor_account_id();
+        let deposit = env::attached_deposit();
+        let token = self.internal_get_extendable_token(&token_id);
+
+        let extension = self
+            .loan_extensions
+            .get(&token_id)
+            .unwrap_or_else(|| ContractError::NotFound(format!("extension proposed for {}", token_id)).panic());
+        require(account_id != extension.proposer, || {
+            ContractError::Unauthorized("Proposer can't accept own extension.".to_string())
+        });
+
+        let payment = if account_id == token.owner_id {
+            let required = self.internal_extension_payment(&token, extension.fee.0, extension.capitalize_interest);
+            require(deposit >= required, || {
+                ContractError::WrongDeposit(format!("Attached deposit {} doesn't cover the extension payment {}.", deposit, required))
+            });
+            deposit
+        } else if Some(&account_id) == token.creditor.as_ref() {
+            require(deposit == 0, || ContractError::WrongDeposit("Creditor doesn't pay for an extension.".to_string()));
+            extension.escrow.0
+        } else {
+            ContractError::Unauthorized("Only borrower or creditor can accept an extension.".to_string()).panic()
+        };
+
+        self.internal_remove_loan_extension(&token_id);
+        self.internal_apply_loan_extension(token, extension, payment);
+    }
+
+    /// Withdraws or rejects a proposal, refunding the borrower's escrow.
+    pub fn cancel_loan_extension(&mut self, token_id: TokenId) {
+        let account_id = env::predecessor_account_id();
+        let token = self.internal_get_extendable_token(&token_id);
+        require(account_id == token.owner_id || Some(&account_id) == token.creditor.as_ref(), || {
+            ContractError::Unauthorized("Only borrower or creditor can cancel an extension.".to_string())
+        });
+
+        require(self.internal_drop_loan_extension(&token_id), || {
+            ContractError::NotFound(format!("extension proposed for {}", token_id))
+        });
+    }
+
+    pub fn get_loan_extension(&self, token_id: TokenId) -> Option<LoanExtension> {
+        self.loan_extensions.get(&token_id)
+    }
+}
+
+impl Contract {
+    fn internal_get_extendable_token(&self, token_id: &TokenId) -> LockedToken {
+        let token = self.internal_get_token(token_id);
+        require_state(&token, LockedTokenState::Locked);
+        assert_token_currency(&token, &None);
+        self.assert_not_liquidating(token_id);
+        token
+    }
+
+    fn internal_extension_payment(&self, token: &LockedToken, fee: u128, capitalize_interest: bool) -> u128 {
+        if capitalize_interest {
+            fee
+        } else {
+            fee + self.internal_repayment_quote(token).interest.0
+        }
+    }
+
+    /// Removes a pending proposal, its storage is released to the proposer.
+    fn internal_remove_loan_extension(&mut self, token_id: &TokenId) -> Option<LoanExtension> {
+        let initial_storage_usage = env::storage_usage();
+        let extension = self.loan_extensions.remove(token_id);
+        if let Some(extension) = &extension {
+            self.internal_charge_storage(&extension.proposer, initial_storage_usage);
+        }
+        extension
+    }
+
+    /// Removes a pending proposal and refunds its escrow to the borrower.
+    pub(crate) fn internal_drop_loan_extension(&mut self, token_id: &TokenId) -> bool {
+        if let Some(extension) = self.internal_remove_loan_extension(token_id) {
+            if extension.escrow.0 > 0 {
+                let token = self.internal_get_token(token_id);
+                Promise::new(token.owner_id).transfer(extension.escrow.0);
+            }
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Interest accrued after an escrowed proposal was made stays accrued.
+    fn internal_apply_loan_extension(&mut self, mut token: LockedToken, extension: LoanExtension, payment: u128) {
+        self.internal_accrue_interest(&mut token);
+
+        let mut to_creditor = extension.fee.0;
+        let mut rest = payment - extension.fee.0;
+        if extension.capitalize_interest {
+            token.outstanding_principal = U128(token.outstanding_principal.0 + token.accrued_interest.0);
+            token.accrued_interest = U128(0);
+        } else {
+            let interest_paid = std::cmp::min(rest, token.accrued_interest.0);
+            token.accrued_interest = U128(token.accrued_interest.0 - interest_paid);
+            to_creditor += interest_paid;
+            rest -= interest_paid;
+        }
+
+        token.duration = extension.new_duration.0;
+        if let Some(last_installment) = token.installments.last_mut() {
+            last_installment.due_after = extension.new_duration;
+        }
+
+        ContractEvent::LoanExtended(ExtensionData { token: TokenData::from_token(&token), duration: token.duration }).emit();
+
+        self.token_id_to_locked_token.insert(&token.token_id, &token);
+        if rest > 0 {
+            Promise::new(token.owner_id.clone()).transfer(rest);
+        }
+        if to_creditor > 0 {
+            let fee = self.internal_collect_fee(&token.currency, to_creditor, self.interest_fee_bps);
+            self.internal_pay_creditor(&token, &token.creditor_id(), to_creditor - fee);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    const LISTING_MSG: &str = r#"{"principal": "1000000000000000000000000", "apr_bps": 1000, "duration": 3600, "title": "Listing"}"#;
+    const INSTALLMENTS_MSG: &str = r#"{"principal": "1000000000000000000000000", "apr_bps": 1000, "duration": 3600, "title": "Listing",
+        "installments": [{"due_after": "1800", "principal_bps": 5000}, {"due_after": "3600", "principal_bps": 5000}]}"#;
+
+    fn setup_loan(msg: &str) -> (Contract, TokenId) {
+        let mut contract = setup_contract();
+        register(&mut contract, ALICE, NEAR);
+        register(&mut contract, BOB, NEAR);
+        let token_id = list_nft(&mut contract, ALICE, "1", msg);
+        fund_listing(&mut contract, BOB, &token_id, NEAR, 0);
+        (contract, token_id)
+    }
+
+    #[test]
+    fn capitalized_extension_adds_the_interest_to_the_principal() {
+        let (mut contract, token_id) = setup_loan(LISTING_MSG);
+        set_context(ALICE, 0, 900);
+        let interest = contract.get_repayment_quote(token_id.clone()).interest.0;
+        set_context(ALICE, NEAR / 100, 900);
+        contract.propose_loan_extension(token_id.clone(), 7_200, U128(NEAR / 100), true);
+        set_context(BOB, 0, 900);
+        contract.accept_loan_extension(token_id.clone());
+
+        let token = contract.internal_get_token(&token_id);
+        assert_eq!((token.duration, token.outstanding_principal.0, token.accrued_interest.0), (7_200, NEAR + interest, 0));
+        assert!(contract.get_loan_extension(token_id).is_none());
+    }
+
+    #[test]
+    fn paid_extension_keeps_the_installment_schedule_quotable() {
+        let (mut contract, token_id) = setup_loan(INSTALLMENTS_MSG);
+        set_context(ALICE, 0, 900);
+        let interest = contract.get_repayment_quote(token_id.clone()).interest.0;
+        set_context(ALICE, interest + NEAR / 100, 900);
+        contract.propose_loan_extension(token_id.clone(), 7_200, U128(NEAR / 100), false);
+        set_context(BOB, 0, 900);
+        contract.accept_loan_extension(token_id.clone());
+
+        let token = contract.internal_get_token(&token_id);
+        assert_eq!((token.outstanding_principal.0, token.accrued_interest.0), (NEAR, 0));
+        assert_eq!(token.installments.last().unwrap().due_after, U64(7_200));
+        set_context(ALICE, 0, 1_000);
+        let quote = contract.get_repayment_quote(token_id);
+        assert_eq!(quote.next_installment.unwrap().principal_due.0, NEAR / 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "Unsupported")]
+    fn loans_with_installments_cant_capitalize_interest() {
+        let (mut contract, token_id) = setup_loan(INSTALLMENTS_MSG);
+        set_context(BOB, 0, 900);
+        contract.propose_loan_extension(token_id, 7_200, U128(0), true);
+    }
+
+    #[test]
+    #[should_panic(expected = "WrongPhase")]
+    fn liquidating_loans_cant_be_extended() {
+        let (mut contract, token_id) = setup_loan(LISTING_MSG);
+        contract.liquidations.insert(&token_id, &Liquidation {
+            kind: LiquidationKind::English,
+            debt: U128(NEAR),
+            started_at: U64(0),
+            deadline: U64(1),
+            best_bid: None,
+        });
+        set_context(BOB, 0, 900);
+        contract.propose_loan_extension(token_id, 7_200, U128(0), false);
+    }
+}
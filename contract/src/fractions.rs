@@ -0,0 +1,612 @@
+use crate::*;
+use near_sdk::json_types::U128;
+use near_sdk::serde_json::json;
+
+/// Storage key of the NEP-141 wasm deployed for every share token.
+const SHARE_TOKEN_CODE_KEY: &[u8] = b"SHARE_TOKEN_CODE";
+/// Balance a share token account keeps on top of its code storage.
+const SHARE_TOKEN_STORAGE_MARGIN: u128 = 1_000_000_000_000_000_000_000_000;
+/// At most this many shareholders can lock shares in votes on one default.
+pub const MAX_SHARE_VOTES: usize = 50;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub enum FractionStatus {
+    /// The share token is being deployed.
+    Deploying,
+    /// Shares are sold to lenders, the listing stays on `Sale`.
+    Funding,
+    /// All shares are sold and the share token is the loan's creditor.
+    Active,
+    /// The loan ended, shares are redeemed for `proceeds` pro rata.
+    Settled,
+}
+
+/// What shareholders decide to do with the collateral of a defaulted loan.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum DefaultDecision {
+    /// Sell the NFT to anyone paying at least `min_price` with
+    /// `buy_defaulted_collateral`.
+    Sell { min_price: U128 },
+    /// Transfer the NFT to `receiver_id`.
+    Redeem { receiver_id: AccountId },
+}
+
+/// Shares locked with the contract as a vote on the default decision.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ShareVote {
+    pub voter: AccountId,
+    pub shares: U128,
+    pub decision: DefaultDecision,
+}
+
+/// Loan on a `Sale` listing funded by the holders of a NEP-141 share token.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Fractionalization {
+    pub token_id: TokenId,
+    pub share_token: AccountId,
+    pub total_shares: U128,
+    /// Principal paid for one share.
+    pub share_price: U128,
+    /// Shares bought, including those still being transferred.
+    pub sold_shares: U128,
+    /// Shares delivered to their buyers.
+    pub funded_shares: U128,
+    pub status: FractionStatus,
+    /// Repayments and sale proceeds not yet redeemed.
+    pub proceeds: U128,
+    /// Shares not yet redeemed for `proceeds`.
+    pub outstanding_shares: U128,
+    pub votes: Vec<ShareVote>,
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Stores the NEP-141 wasm deployed for share tokens. The code is passed
+    /// as the raw input of the call. The token must be initializable with
+    /// `new(owner_id, total_supply, metadata)`.
+    pub fn set_share_token_code(&mut self) {
+        self.assert_owner();
+        let code = env::input()
+            .unwrap_or_else(|| ContractError::InvalidArgument("Share token code is missing.".to_string()).panic());
+        env::storage_write(SHARE_TOKEN_CODE_KEY, &code);
+    }
+
+    /// Deploys a share token for a fixed-terms NEAR listing, splitting its
+    /// principal in `total_shares` equal shares. The attached deposit funds
+    /// the share token account.
+    #[payable]
+    pub fn fractionalize(&mut self, token_id: TokenId, total_shares: U128) -> AccountId {
+        let deposit = env::attached_deposit();
+        let token = self.internal_get_token(&token_id);
+
+        require(env::predecessor_account_id() == token.owner_id, || {
+            ContractError::Unauthorized("Only owner can fractionalize a listing.".to_string())
+        });
+        require_state(&token, LockedTokenState::Sale);
+        require(token.auction.is_none(), || {
+            ContractError::Unsupported("Auction listings can't be fractionalized.".to_string())
+        });
+        require(token.bundle.is_empty(), || {
+            ContractError::Unsupported("Bundle listings can't be fractionalized.".to_string())
+        });
+        require(token.liquidation.is_none(), || {
+            ContractError::Unsupported("Listings liquidated by auction can't be fractionalized.".to_string())
+        });
+        assert_token_currency(&token, &None);
+        require(!self.fraction_by_token.contains_key(&token_id), || {
+            ContractError::Conflict(format!("Token {} is already fractionalized.", token_id))
+        });
+
+        let principal = token.outstanding_principal.0;
+        require(total_shares.0 > 0 && principal.is_multiple_of(total_shares.0), || {
+            ContractError::InvalidArgument(format!("Principal {} must split in {} equal shares.", principal, total_shares.0))
+        });
+
+        let code = env::storage_read(SHARE_TOKEN_CODE_KEY)
+            .unwrap_or_else(|| ContractError::NotFound("share token code, call set_share_token_code".to_string()).panic());
+        let required = code.len() as u128 * env::storage_byte_cost() + SHARE_TOKEN_STORAGE_MARGIN;
+        require(deposit >= required, || {
+            ContractError::WrongDeposit(format!("Attach {} to deploy the share token.", required))
+        });
+
+        self.internal_reserve_storage(&token.owner_id, LOAN_STORAGE_BYTES);
+        let share_token = format!("shares-{}.{}", self.next_fraction_id, env::current_account_id());
+        self.next_fraction_id += 1;
+
+        self.fractions.insert(&share_token, &Fractionalization {
+            token_id: token_id.clone(),
+            share_token: share_token.clone(),
+            total_shares,
+            share_price: U128(principal / total_shares.0),
+            sold_shares: U128(0),
+            funded_shares: U128(0),
+            status: FractionStatus::Deploying,
+            proceeds: U128(0),
+            outstanding_shares: total_shares,
+            votes: vec![],
+        });
+        self.fraction_by_token.insert(&token_id, &share_token);
+
+        let metadata = json!({
+            "spec": "ft-1.0.0",
+            "name": format!("{} shares", token.title),
+            "symbol": format!("SHARES{}", self.next_fraction_id - 1),
+            "decimals": 0,
+        });
+        let args = json!({
+            "owner_id": env::current_account_id(),
+            "total_supply": total_shares,
+            "metadata": metadata,
+        });
+
+        Promise::new(share_token.clone())
+            .create_account()
+            .transfer(deposit)
+            .deploy_contract(code)
+            .function_call(b"new".to_vec(),
+                           args.to_string().into_bytes(),
+                           0,
+                           GAS_FOR_SHARE_TOKEN_INIT)
+            .then(ext_self::resolve_share_token_deploy(share_token.clone(),
+                                                       token.owner_id,
+                                                       U128(deposit),
+                                                       &env::current_account_id(),
+                                                       0,
+                                                       GAS_FOR_RESOLVE_SHARE_TOKEN));
+        share_token
+    }
+
+    #[private]
+    pub fn resolve_share_token_deploy(&mut self, share_token: AccountId, owner_id: AccountId, deposit: U128) -> bool {
+        let mut fraction = self.internal_get_fraction(&share_token);
+        if is_promise_success() {
+            fraction.status = FractionStatus::Funding;
+            self.fractions.insert(&share_token, &fraction);
+            return true;
+        }
+
+        env::log(format!("Failed to deploy share token {}, deposit refunded.", share_token).as_bytes());
+        self.fractions.remove(&share_token);
+        self.fraction_by_token.remove(&fraction.token_id);
+        self.internal_release_storage(&owner_id, LOAN_STORAGE_BYTES);
+        Promise::new(owner_id).transfer(deposit.0);
+        false
+    }
+
+    /// Buys `shares` at the share price. The buyer has to be registered with
+    /// the share token. The loan starts once every share is delivered.
+    #[payable]
+    pub fn buy_shares(&mut self, share_token: AccountId, shares: U128) {
+        let buyer = env::predecessor_account_id();
+        let deposit = env::attached_deposit();
+        let mut fraction = self.internal_get_fraction(&share_token);
+
+        require(fraction.status == FractionStatus::Funding, || {
+            ContractError::WrongPhase(format!("Shares of {} aren't on sale.", share_token))
+        });
+        require(shares.0 > 0, || ContractError::InvalidArgument("Buy at least one share.".to_string()));
+        require(fraction.sold_shares.0 + shares.0 <= fraction.total_shares.0, || {
+            ContractError::LimitExceeded(format!("Only {} shares are left.", fraction.total_shares.0 - fraction.sold_shares.0))
+        });
+        require(deposit == shares.0 * fraction.share_price.0, || {
+            ContractError::WrongDeposit(format!("Attach the price of {} shares.", shares.0))
+        });
+
+        let token = self.internal_get_token(&fraction.token_id);
+        require(buyer != token.owner_id, || ContractError::Unauthorized("Owner can't fund own listing.".to_string()));
+
+        fraction.sold_shares = U128(fraction.sold_shares.0 + shares.0);
+        self.fractions.insert(&share_token, &fraction);
+
+        fungible_token::ft_transfer(ValidAccountId::try_from(buyer.clone()).unwrap(),
+                                    shares,
+                                    None,
+                                    &share_token,
+                                    1,
+                                    GAS_FOR_FT_TRANSFER)
+            .then(ext_self::resolve_share_purchase(share_token,
+                                                   buyer,
+                                                   shares,
+                                                   &env::current_account_id(),
+                                                   0,
+                                                   GAS_FOR_RESOLVE_SHARE_PURCHASE));
+    }
+
+    /// Starts the loan with the share token as creditor once all shares are
+    /// delivered, refunds the buyer if the delivery failed.
+    #[private]
+    pub fn resolve_share_purchase(&mut self, share_token: AccountId, buyer: AccountId, shares: U128) -> bool {
+        let mut fraction = self.internal_get_fraction(&share_token);
+        if !is_promise_success() {
+            env::log(format!("Failed to deliver {} shares of {} to {}, refunded.", shares.0, share_token, buyer).as_bytes());
+            fraction.sold_shares = U128(fraction.sold_shares.0 - shares.0);
+            self.fractions.insert(&share_token, &fraction);
+            Promise::new(buyer).transfer(shares.0 * fraction.share_price.0);
+            return false;
+        }
+
+        fraction.funded_shares = U128(fraction.funded_shares.0 + shares.0);
+        if fraction.funded_shares == fraction.total_shares {
+            fraction.status = FractionStatus::Active;
+            let token = self.internal_get_token(&fraction.token_id);
+            let principal = token.outstanding_principal.0;
+            let apr_bps = token.apr_bps;
+            self.internal_release_storage(&token.owner_id, LOAN_STORAGE_BYTES);
+            self.internal_lock_token(token, &share_token, principal, apr_bps);
+        }
+        self.fractions.insert(&share_token, &fraction);
+        true
+    }
+
+    /// Buys the collateral of a defaulted loan at the lowest price a majority
+    /// of shares voted to sell at. The excess deposit is refunded.
+    #[payable]
+    pub fn buy_defaulted_collateral(&mut self, share_token: AccountId) {
+        let buyer = env::predecessor_account_id();
+        let deposit = env::attached_deposit();
+        let fraction = self.internal_get_fraction(&share_token);
+        self.internal_get_defaulted_token(&fraction);
+
+        let price = self
+            .internal_approved_sale_price(&fraction)
+            .unwrap_or_else(|| ContractError::WrongPhase("Shareholders haven't agreed on a sale price.".to_string()).panic());
+        require(deposit >= price, || {
+            ContractError::WrongDeposit(format!("Attached deposit {} doesn't cover the sale price {}.", deposit, price))
+        });
+
+        if deposit > price {
+            Promise::new(buyer.clone()).transfer(deposit - price);
+        }
+        self.internal_release_defaulted_collateral(&fraction, &buyer, price);
+    }
+
+    /// Returns the caller's locked vote shares.
+    pub fn withdraw_share_vote(&mut self, share_token: AccountId) {
+        let voter = env::predecessor_account_id();
+        let mut fraction = self.internal_get_fraction(&share_token);
+
+        let index = fraction
+            .votes
+            .iter()
+            .position(|vote| vote.voter == voter)
+            .unwrap_or_else(|| ContractError::NotFound(format!("vote of {} on {}", voter, share_token)).panic());
+        let vote = fraction.votes.swap_remove(index);
+        self.fractions.insert(&share_token, &fraction);
+
+        self.internal_pay(&Some(share_token), &voter, vote.shares.0);
+    }
+
+    /// Removes the loan once the collateral reached the buyer or receiver,
+    /// otherwise restores it and refunds the buyer.
+    #[private]
+    pub fn resolve_defaulted_collateral(&mut self, share_token: AccountId, receiver_id: AccountId, price: U128) -> bool {
+        let mut fraction = self.internal_get_fraction(&share_token);
+        if is_promise_success() {
+            let token = self.internal_get_token(&fraction.token_id);
+            ContractEvent::CollateralReleased(ReleaseData {
+                token: TokenData::from_token(&token),
+                receiver_id,
+                price: Some(price),
+            }).emit();
+
+            fraction.proceeds = U128(fraction.proceeds.0 + price.0);
+            self.fractions.insert(&share_token, &fraction);
+            self.internal_remove_locked_token(&fraction.token_id);
+            return true;
+        }
+
+        env::log(format!("Failed to transfer {} to {}, state restored to Locked.", fraction.token_id, receiver_id).as_bytes());
+        let mut token = self.internal_get_token(&fraction.token_id);
+        self.internal_set_state(&mut token, LockedTokenState::Locked);
+        self.token_id_to_locked_token.insert(&fraction.token_id, &token);
+        if price.0 > 0 {
+            Promise::new(receiver_id).transfer(price.0);
+        }
+        false
+    }
+
+    pub fn get_fractionalization(&self, share_token: AccountId) -> Option<Fractionalization> {
+        self.fractions.get(&share_token)
+    }
+
+    pub fn get_token_fractionalization(&self, token_id: TokenId) -> Option<Fractionalization> {
+        self.fraction_by_token
+            .get(&token_id)
+            .and_then(|share_token| self.fractions.get(&share_token))
+    }
+}
+
+impl Contract {
+    fn internal_get_fraction(&self, share_token: &AccountId) -> Fractionalization {
+        self.fractions
+            .get(share_token)
+            .unwrap_or_else(|| ContractError::NotFound(format!("share token {}", share_token)).panic())
+    }
+
+    pub(crate) fn is_fractionalized(&self, token_id: &TokenId) -> bool {
+        self.fraction_by_token.contains_key(token_id)
+    }
+
+    /// A fractionalized listing can only be cancelled before any share is sold.
+    pub(crate) fn assert_no_shares_sold(&self, token_id: &TokenId) {
+        if let Some(share_token) = self.fraction_by_token.get(token_id) {
+            let fraction = self.internal_get_fraction(&share_token);
+            require(fraction.status == FractionStatus::Funding && fraction.sold_shares.0 == 0, || {
+                ContractError::Conflict(format!("Shares of {} are being sold.", token_id))
+            });
+        }
+    }
+
+    /// Repayments to a share token creditor are kept for its shareholders,
+    /// those to a pool go back into the pool.
+    pub(crate) fn internal_pay_creditor(&mut self, token: &LockedToken, creditor: &AccountId, amount: u128) {
+        if let Some(pool_id) = pool_id_from_account(creditor) {
+            return self.internal_credit_pool(pool_id, &token.token_id, amount);
+        }
+        match self.fractions.get(creditor) {
+            Some(mut fraction) if fraction.status == FractionStatus::Active => {
+                fraction.proceeds = U128(fraction.proceeds.0 + amount);
+                self.fractions.insert(creditor, &fraction);
+            }
+            _ => self.internal_pay(&token.currency, creditor, amount),
+        }
+    }
+
+    /// Called when the loan left the contract or got a new creditor. A
+    /// listing withdrawn before its shares sold releases what its owner set
+    /// aside for the loan.
+    pub(crate) fn internal_settle_fraction(&mut self, token_id: &TokenId, owner_id: &AccountId) {
+        if let Some(share_token) = self.fraction_by_token.remove(token_id) {
+            let mut fraction = self.internal_get_fraction(&share_token);
+            if fraction.status != FractionStatus::Active {
+                self.internal_release_storage(owner_id, LOAN_STORAGE_BYTES);
+            }
+            fraction.status = FractionStatus::Settled;
+            self.fractions.insert(&share_token, &fraction);
+        }
+    }
+
+    /// Handles shares sent back with `ft_transfer_call`. While funding they
+    /// are refunded at the share price, once settled they are redeemed for
+    /// their part of the proceeds.
+    pub(crate) fn internal_redeem_shares(&mut self, share_token: &AccountId, holder: &AccountId, shares: u128) {
+        let mut fraction = self.internal_get_fraction(share_token);
+        let payout = match fraction.status {
+            FractionStatus::Funding => {
+                fraction.sold_shares = U128(fraction.sold_shares.0 - shares);
+                fraction.funded_shares = U128(fraction.funded_shares.0 - shares);
+                shares * fraction.share_price.0
+            }
+            FractionStatus::Settled => {
+                let payout = mul_div(fraction.proceeds.0, shares, fraction.outstanding_shares.0);
+                fraction.proceeds = U128(fraction.proceeds.0 - payout);
+                fraction.outstanding_shares = U128(fraction.outstanding_shares.0 - shares);
+                payout
+            }
+            _ => ContractError::WrongPhase(format!("Shares of {} can't be redeemed while the loan runs.", share_token)).panic(),
+        };
+        self.fractions.insert(share_token, &fraction);
+
+        env::log(format!("{} redeemed {} shares of {} for {}.", holder, shares, share_token, payout).as_bytes());
+        if payout > 0 {
+            Promise::new(holder.clone()).transfer(payout);
+        }
+    }
+
+    /// Locks `shares` sent with `ft_transfer_call` as a vote. A redemption
+    /// backed by a majority of shares is carried out right away.
+    pub(crate) fn internal_vote_on_default(
+        &mut self,
+        share_token: &AccountId,
+        voter: &AccountId,
+        shares: u128,
+        decision: DefaultDecision,
+    ) {
+        let mut fraction = self.internal_get_fraction(share_token);
+        self.internal_get_defaulted_token(&fraction);
+
+        if let Some(vote) = fraction.votes.iter_mut().find(|vote| &vote.voter == voter) {
+            vote.shares = U128(vote.shares.0 + shares);
+            vote.decision = decision.clone();
+        } else {
+            require(fraction.votes.len() < MAX_SHARE_VOTES, || {
+                ContractError::LimitExceeded(format!("At most {} shareholders can vote.", MAX_SHARE_VOTES))
+            });
+            fraction.votes.push(ShareVote { voter: voter.clone(), shares: U128(shares), decision: decision.clone() });
+        }
+        self.fractions.insert(share_token, &fraction);
+
+        if let DefaultDecision::Redeem { receiver_id } = decision {
+            let backing: u128 = fraction
+                .votes
+                .iter()
+                .filter(|vote| vote.decision == DefaultDecision::Redeem { receiver_id: receiver_id.clone() })
+                .map(|vote| vote.shares.0)
+                .sum();
+            if backing * 2 > fraction.total_shares.0 {
+                self.internal_release_defaulted_collateral(&fraction, &receiver_id, 0);
+            }
+        }
+    }
+
+    fn internal_get_defaulted_token(&self, fraction: &Fractionalization) -> LockedToken {
+        require(fraction.status == FractionStatus::Active, || {
+            ContractError::WrongPhase(format!("Loan of {} isn't running.", fraction.share_token))
+        });
+        let token = self.internal_get_token(&fraction.token_id);
+        require_state(&token, LockedTokenState::Locked);
+        require(self.check_is_token_delayed(token.clone()), || ContractError::NotOverdue(fraction.token_id.clone()));
+        token
+    }
+
+    /// Lowest price a majority of shares agreed to sell at.
+    fn internal_approved_sale_price(&self, fraction: &Fractionalization) -> Option<u128> {
+        let mut offers: Vec<(u128, u128)> = fraction
+            .votes
+            .iter()
+            .filter_map(|vote| match &vote.decision {
+                DefaultDecision::Sell { min_price } => Some((min_price.0, vote.shares.0)),
+                _ => None,
+            })
+            .collect();
+        offers.sort();
+
+        let mut backing = 0;
+        for (min_price, shares) in offers {
+            backing += shares;
+            if backing * 2 > fraction.total_shares.0 {
+                return Some(min_price);
+            }
+        }
+        None
+    }
+
+    fn internal_release_defaulted_collateral(&mut self, fraction: &Fractionalization, receiver_id: &AccountId, price: u128) {
+        let mut token = self.internal_get_token(&fraction.token_id);
+        self.internal_set_state(&mut token, LockedTokenState::TransferToCreditor);
+        self.token_id_to_locked_token.insert(&fraction.token_id, &token);
+
+        transfer_bundle_item(&fraction.token_id, receiver_id)
+            .then(ext_self::resolve_defaulted_collateral(fraction.share_token.clone(),
+                                                         receiver_id.clone(),
+                                                         U128(price),
+                                                         &env::current_account_id(),
+                                                         0,
+                                                         GAS_FOR_RESOLVE_NFT_RETURN));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use near_sdk::PromiseResult;
+
+    const LISTING_MSG: &str = r#"{"principal": "1000000000000000000000000", "apr_bps": 1000, "duration": 3600, "title": "Listing",
+        "interest_model": "FlatFee"}"#;
+    const SHARE_PRICE: u128 = NEAR / 2;
+
+    /// Fractionalizes a listing of ALICE in two shares and resolves the
+    /// share token deployment with `result`.
+    fn setup_fraction(result: PromiseResult) -> (Contract, TokenId, AccountId) {
+        let mut contract = setup_contract();
+        register(&mut contract, ALICE, NEAR);
+        register(&mut contract, BOB, NEAR);
+        register(&mut contract, CHARLIE, NEAR);
+        env::storage_write(SHARE_TOKEN_CODE_KEY, b"wasm");
+        let token_id = list_nft(&mut contract, ALICE, "1", LISTING_MSG);
+
+        set_context(ALICE, 2 * NEAR, 0);
+        let share_token = contract.fractionalize(token_id.clone(), U128(2));
+        set_callback_context(0, result);
+        contract.resolve_share_token_deploy(share_token.clone(), ALICE.to_string(), U128(2 * NEAR));
+        (contract, token_id, share_token)
+    }
+
+    fn buy_share(contract: &mut Contract, share_token: &AccountId, buyer: &str, result: PromiseResult) -> bool {
+        set_context(buyer, SHARE_PRICE, 0);
+        contract.buy_shares(share_token.clone(), U128(1));
+        set_callback_context(0, result);
+        contract.resolve_share_purchase(share_token.clone(), buyer.to_string(), U128(1))
+    }
+
+    /// `ft_transfer_call` of `shares` by `sender_id` to the contract.
+    fn send_shares(contract: &mut Contract, share_token: &AccountId, sender_id: &str, shares: u128, msg: &str, timestamp_sec: u64) {
+        set_context(share_token, 0, timestamp_sec);
+        contract.ft_on_transfer(account(sender_id), U128(shares), msg.to_string());
+    }
+
+    fn fraction(contract: &Contract, share_token: &AccountId) -> Fractionalization {
+        contract.get_fractionalization(share_token.clone()).unwrap()
+    }
+
+    #[test]
+    fn shares_fund_the_loan_and_redeem_the_repayment() {
+        let (mut contract, token_id, share_token) = setup_fraction(PromiseResult::Successful(vec![]));
+        assert_eq!(fraction(&contract, &share_token).status, FractionStatus::Funding);
+
+        assert!(buy_share(&mut contract, &share_token, BOB, PromiseResult::Successful(vec![])));
+        assert_eq!(contract.internal_get_token(&token_id).state, LockedTokenState::Sale);
+        assert!(buy_share(&mut contract, &share_token, CHARLIE, PromiseResult::Successful(vec![])));
+        assert_eq!(get_transfers(), vec![(ALICE.to_string(), NEAR)]);
+        assert_eq!(fraction(&contract, &share_token).status, FractionStatus::Active);
+        assert_eq!(contract.internal_get_token(&token_id).creditor_id(), share_token);
+
+        set_context(ALICE, NEAR * 11 / 10, 100);
+        contract.repay_loan(token_id.clone());
+        set_callback_context(100, PromiseResult::Successful(vec![]));
+        assert!(contract.resolve_nft_return(token_id.clone(), LockedTokenState::Locked, U128(NEAR * 11 / 10)));
+        let settled = fraction(&contract, &share_token);
+        assert_eq!((settled.status, settled.proceeds.0), (FractionStatus::Settled, NEAR * 11 / 10));
+        assert!(contract.get_token_fractionalization(token_id).is_none());
+
+        send_shares(&mut contract, &share_token, BOB, 1, r#"{"action": "redeem_shares"}"#, 200);
+        assert_eq!(get_transfers(), vec![(BOB.to_string(), NEAR * 11 / 20)]);
+        send_shares(&mut contract, &share_token, CHARLIE, 1, r#"{"action": "redeem_shares"}"#, 200);
+        assert_eq!(get_transfers(), vec![(CHARLIE.to_string(), NEAR * 11 / 20)]);
+        let redeemed = fraction(&contract, &share_token);
+        assert_eq!((redeemed.proceeds.0, redeemed.outstanding_shares.0), (0, 0));
+    }
+
+    #[test]
+    fn undelivered_shares_are_refunded() {
+        let (mut contract, _, share_token) = setup_fraction(PromiseResult::Successful(vec![]));
+        assert!(!buy_share(&mut contract, &share_token, BOB, PromiseResult::Failed));
+        assert_eq!(get_transfers(), vec![(BOB.to_string(), SHARE_PRICE)]);
+        assert_eq!(fraction(&contract, &share_token).sold_shares.0, 0);
+    }
+
+    #[test]
+    fn shares_returned_while_funding_are_refunded_at_the_share_price() {
+        let (mut contract, token_id, share_token) = setup_fraction(PromiseResult::Successful(vec![]));
+        buy_share(&mut contract, &share_token, BOB, PromiseResult::Successful(vec![]));
+
+        send_shares(&mut contract, &share_token, BOB, 1, r#"{"action": "redeem_shares"}"#, 0);
+        assert_eq!(get_transfers(), vec![(BOB.to_string(), SHARE_PRICE)]);
+        let refunded = fraction(&contract, &share_token);
+        assert_eq!((refunded.sold_shares.0, refunded.funded_shares.0), (0, 0));
+        assert_eq!(contract.internal_get_token(&token_id).state, LockedTokenState::Sale);
+    }
+
+    #[test]
+    fn failed_deploy_refunds_the_deposit() {
+        let (contract, token_id, share_token) = setup_fraction(PromiseResult::Failed);
+        assert_eq!(get_transfers(), vec![(ALICE.to_string(), 2 * NEAR)]);
+        assert!(contract.get_fractionalization(share_token).is_none());
+        assert!(!contract.is_fractionalized(&token_id));
+    }
+
+    #[test]
+    fn majority_vote_redeems_the_defaulted_collateral() {
+        let (mut contract, token_id, share_token) = setup_fraction(PromiseResult::Successful(vec![]));
+        buy_share(&mut contract, &share_token, BOB, PromiseResult::Successful(vec![]));
+        buy_share(&mut contract, &share_token, CHARLIE, PromiseResult::Successful(vec![]));
+        let msg = r#"{"action": "vote_on_default", "decision": {"redeem": {"receiver_id": "bob.near"}}}"#;
+
+        send_shares(&mut contract, &share_token, BOB, 1, msg, 3600);
+        assert_eq!(contract.internal_get_token(&token_id).state, LockedTokenState::Locked);
+        send_shares(&mut contract, &share_token, CHARLIE, 1, msg, 3600);
+        assert_eq!(contract.internal_get_token(&token_id).state, LockedTokenState::TransferToCreditor);
+        let transfer = get_function_calls().into_iter().find(|(_, method_name, _)| method_name == "nft_transfer").unwrap();
+        assert_eq!((transfer.0.as_str(), transfer.2["receiver_id"].as_str()), (NFT_CONTRACT_ID, Some(BOB)));
+
+        set_callback_context(3600, PromiseResult::Successful(vec![]));
+        assert!(contract.resolve_defaulted_collateral(share_token.clone(), BOB.to_string(), U128(0)));
+        assert!(contract.token_id_to_locked_token.get(&token_id).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "NotOverdue")]
+    fn votes_wait_for_the_default() {
+        let (mut contract, _, share_token) = setup_fraction(PromiseResult::Successful(vec![]));
+        buy_share(&mut contract, &share_token, BOB, PromiseResult::Successful(vec![]));
+        buy_share(&mut contract, &share_token, CHARLIE, PromiseResult::Successful(vec![]));
+        let msg = r#"{"action": "vote_on_default", "decision": {"sell": {"min_price": "1000000000000000000000000"}}}"#;
+        send_shares(&mut contract, &share_token, BOB, 1, msg, 100);
+    }
+}
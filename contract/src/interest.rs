@@ -0,0 +1,211 @@
+use crate::*;
+
+pub const BPS_DENOMINATOR: u128 = 10_000;
+pub const BPS_PER_PERCENT: u64 = 100;
+pub const SECONDS_PER_YEAR: u64 = 365 * 24 * 60 * 60;
+
+/// Compounding more often than this would make repayment gas unbounded.
+pub const MIN_COMPOUNDING_PERIOD_SEC: u64 = 60 * 60;
+/// 1000%, keeps the interest math within `u128`.
+pub const MAX_APR_BPS: u64 = 100_000;
+
+/// Scale of the fixed-point growth factor of compound interest.
+const COMPOUND_SCALE: u128 = 1_000_000_000_000_000_000;
+
+/// How interest accrues on a loan's principal. Rates are in basis points.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
+#[serde(crate = "near_sdk::serde")]
+pub enum InterestModel {
+    /// `apr_bps` of the principal charged once, however long the loan ran.
+    /// This is how listings created before interest models were charged.
+    FlatFee,
+    /// Annualized simple interest pro-rated by the seconds since the loan started.
+    #[default]
+    Simple,
+    /// Annualized interest compounded every `period` seconds.
+    Compound { period: u64 },
+}
+
+impl InterestModel {
+    pub fn validate(&self) {
+        if let InterestModel::Compound { period } = self {
+            if *period < MIN_COMPOUNDING_PERIOD_SEC {
+                ContractError::InvalidArgument(format!("Compounding period must be at least {} seconds, got {}.",
+                                                       MIN_COMPOUNDING_PERIOD_SEC,
+                                                       period)).panic();
+            }
+        }
+    }
+
+    /// Interest owed on `principal` after `elapsed_sec` seconds.
+    pub fn interest(&self, principal: u128, apr_bps: u64, elapsed_sec: u64) -> u128 {
+        match self {
+            InterestModel::FlatFee => mul_div(principal, apr_bps as u128, BPS_DENOMINATOR),
+            InterestModel::Simple => simple_interest(principal, apr_bps, elapsed_sec),
+            InterestModel::Compound { period } => {
+                let balance = compound_balance(principal, apr_bps, *period, elapsed_sec).unwrap_or_else(|| {
+                    ContractError::LimitExceeded(format!("Compound interest on {} overflows after {} seconds.",
+                                                         principal,
+                                                         elapsed_sec)).panic()
+                });
+                balance - principal
+            }
+        }
+    }
+}
+
+/// Panics unless `apr_bps` is positive and at most `MAX_APR_BPS`. `name`
+/// says whose rate it is in the error.
+pub fn validate_apr_bps(apr_bps: u64, name: &str) {
+    if apr_bps == 0 || apr_bps > MAX_APR_BPS {
+        ContractError::InvalidArgument(format!("{} apr_bps must be within (0, {}], got {}.", name, MAX_APR_BPS, apr_bps)).panic();
+    }
+}
+
+/// Principal plus interest compounded every `period` seconds, with simple
+/// interest on the balance for the last partial period. `None` on overflow.
+fn compound_balance(principal: u128, apr_bps: u64, period: u64, elapsed_sec: u64) -> Option<u128> {
+    let rate = COMPOUND_SCALE + simple_interest(COMPOUND_SCALE, apr_bps, period);
+    let mut factor = COMPOUND_SCALE;
+    let mut base = rate;
+    let mut periods = elapsed_sec / period;
+    while periods > 0 {
+        if periods & 1 == 1 {
+            factor = checked_mul_div(factor, base, COMPOUND_SCALE)?;
+        }
+        periods >>= 1;
+        if periods > 0 {
+            base = checked_mul_div(base, base, COMPOUND_SCALE)?;
+        }
+    }
+    let balance = checked_mul_div(principal, factor, COMPOUND_SCALE)?;
+    balance.checked_add(simple_interest(balance, apr_bps, elapsed_sec % period))
+}
+
+fn simple_interest(principal: u128, apr_bps: u64, elapsed_sec: u64) -> u128 {
+    mul_div(principal,
+            apr_bps as u128 * elapsed_sec as u128,
+            BPS_DENOMINATOR * SECONDS_PER_YEAR as u128)
+}
+
+/// `a * b / c` rounded down. The product is kept in 256 bits, so only the
+/// result has to fit into `u128`.
+pub fn mul_div(a: u128, b: u128, c: u128) -> u128 {
+    checked_mul_div(a, b, c).unwrap_or_else(|| {
+        ContractError::LimitExceeded(format!("{} * {} / {} overflows.", a, b, c)).panic()
+    })
+}
+
+/// `mul_div` returning `None` instead of overflowing.
+fn checked_mul_div(a: u128, b: u128, c: u128) -> Option<u128> {
+    let (high, low) = wide_mul(a, b);
+    if high >= c {
+        return None;
+    }
+    // Long division of `high:low` by `c`, one bit of `low` at a time.
+    let mut remainder = high;
+    let mut quotient = 0;
+    for bit in (0..128).rev() {
+        let carry = remainder >> 127;
+        remainder = remainder << 1 | (low >> bit & 1);
+        if carry == 1 || remainder >= c {
+            remainder = remainder.wrapping_sub(c);
+            quotient |= 1 << bit;
+        }
+    }
+    Some(quotient)
+}
+
+/// Full 256-bit product of `a` and `b` as its high and low halves.
+fn wide_mul(a: u128, b: u128) -> (u128, u128) {
+    const MASK: u128 = u64::MAX as u128;
+    let (a_high, a_low) = (a >> 64, a & MASK);
+    let (b_high, b_low) = (b >> 64, b & MASK);
+
+    let low_low = a_low * b_low;
+    let high_low = a_high * b_low;
+    let low_high = a_low * b_high;
+    let middle = (low_low >> 64) + (high_low & MASK) + (low_high & MASK);
+    let low = (middle << 64) | (low_low & MASK);
+    let high = a_high * b_high + (high_low >> 64) + (low_high >> 64) + (middle >> 64);
+    (high, low)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    const PRINCIPAL: u128 = 1_000 * NEAR;
+
+    /// Compounds period by period, the way `interest` used to.
+    fn compound_by_period(principal: u128, apr_bps: u64, period: u64, elapsed_sec: u64) -> u128 {
+        let mut balance = principal;
+        for _ in 0..elapsed_sec / period {
+            balance += simple_interest(balance, apr_bps, period);
+        }
+        balance += simple_interest(balance, apr_bps, elapsed_sec % period);
+        balance - principal
+    }
+
+    #[test]
+    fn flat_fee_ignores_elapsed_time() {
+        assert_eq!(InterestModel::FlatFee.interest(PRINCIPAL, 500, 0), 50 * NEAR);
+        assert_eq!(InterestModel::FlatFee.interest(PRINCIPAL, 500, 10 * SECONDS_PER_YEAR), 50 * NEAR);
+    }
+
+    #[test]
+    fn simple_interest_is_pro_rated() {
+        let model = InterestModel::Simple;
+        assert_eq!(model.interest(PRINCIPAL, 1_000, 0), 0);
+        assert_eq!(model.interest(PRINCIPAL, 1_000, SECONDS_PER_YEAR / 2), 50 * NEAR);
+        assert_eq!(model.interest(PRINCIPAL, 1_000, SECONDS_PER_YEAR), 100 * NEAR);
+    }
+
+    #[test]
+    fn compound_interest_matches_compounding_by_period() {
+        let period = SECONDS_PER_YEAR / 12;
+        let model = InterestModel::Compound { period };
+        assert_eq!(model.interest(PRINCIPAL, 1_200, 0), 0);
+        assert_eq!(model.interest(PRINCIPAL, 1_200, period / 2), simple_interest(PRINCIPAL, 1_200, period / 2));
+
+        for elapsed_sec in [period, SECONDS_PER_YEAR, SECONDS_PER_YEAR + period / 3, 5 * SECONDS_PER_YEAR] {
+            let interest = model.interest(PRINCIPAL, 1_200, elapsed_sec);
+            let expected = compound_by_period(PRINCIPAL, 1_200, period, elapsed_sec);
+            assert!(interest.abs_diff(expected) <= expected / 1_000_000_000, "{} vs {} after {} seconds", interest, expected, elapsed_sec);
+        }
+        // 1% a month for a year.
+        let interest = model.interest(PRINCIPAL, 1_200, SECONDS_PER_YEAR);
+        assert!(interest > 126_825 * NEAR / 1_000 && interest < 126_826 * NEAR / 1_000);
+    }
+
+    #[test]
+    fn compound_interest_at_the_apr_cap_fits() {
+        let model = InterestModel::Compound { period: MIN_COMPOUNDING_PERIOD_SEC };
+        let interest = model.interest(PRINCIPAL, MAX_APR_BPS, 2 * SECONDS_PER_YEAR);
+        assert!(interest > 20_000 * NEAR);
+    }
+
+    #[test]
+    #[should_panic(expected = "LimitExceeded")]
+    fn compound_interest_overflow_panics() {
+        set_context(ALICE, 0, 0);
+        InterestModel::Compound { period: MIN_COMPOUNDING_PERIOD_SEC }.interest(PRINCIPAL, MAX_APR_BPS, 100 * SECONDS_PER_YEAR);
+    }
+
+    #[test]
+    fn mul_div_keeps_the_full_product() {
+        assert_eq!(mul_div(7, 5, 2), 17);
+        assert_eq!(mul_div(4 * NEAR, 10 * NEAR, 8 * NEAR), 5 * NEAR);
+        assert_eq!(mul_div(u128::MAX, u128::MAX, u128::MAX), u128::MAX);
+        assert_eq!(mul_div(u128::MAX, 3, 4), u128::MAX / 4 * 3 + 2);
+        assert_eq!(checked_mul_div(u128::MAX, 2, 1), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "InvalidArgument")]
+    fn apr_above_the_cap_is_rejected() {
+        set_context(ALICE, 0, 0);
+        validate_apr_bps(MAX_APR_BPS + 1, "Listing");
+    }
+}
@@ -0,0 +1,285 @@
+use crate::*;
+use near_sdk::json_types::U128;
+use near_sdk::serde_json::{self, Value};
+
+/// Loan a lender is willing to give against `token_id` of `market`, or against
+/// any token of `market` if `token_id` is not set. The principal is escrowed
+/// by the contract until the offer is accepted or cancelled.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct LenderOffer {
+    pub id: u64,
+    pub lender: AccountId,
+    pub market: AccountId,
+    pub token_id: Option<TokenId>,
+    pub principal: U128,
+    pub apr_bps: u64,
+    pub duration: u64,
+    pub interest_model: InterestModel,
+}
+
+/// `nft_approve` msg accepting a lender offer. Display fields are stored on
+/// the resulting `LockedToken` like those of a `ListingRequest`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AcceptLenderOfferRequest {
+    pub lender_offer_id: u64,
+    #[serde(default)]
+    pub extra: String,
+    #[serde(default)]
+    pub market_type: String,
+    #[serde(default)]
+    pub title: String,
+    #[serde(default)]
+    pub media: String,
+}
+
+impl AcceptLenderOfferRequest {
+    /// Returns `None` for msgs that don't name a `lender_offer_id`.
+    pub fn from_msg(msg: &str) -> Option<Self> {
+        let value = serde_json::from_str::<Value>(msg).ok()?;
+        value.get("lender_offer_id")?;
+
+        Some(serde_json::from_value(value).unwrap_or_else(|e| {
+            ContractError::InvalidMsg(format!("Invalid lender offer msg: {}", e)).panic()
+        }))
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Escrows the attached deposit as the principal of a new offer. The
+    /// lender's storage for the offer and the credit it turns into is
+    /// reserved until the offer is accepted or cancelled.
+    #[payable]
+    pub fn create_lender_offer(
+        &mut self,
+        market: ValidAccountId,
+        token_id: Option<TokenId>,
+        apr_bps: u64,
+        duration: u64,
+        interest_model: Option<InterestModel>,
+    ) -> u64 {
+        let principal = env::attached_deposit();
+        require(principal > 0, || ContractError::WrongDeposit("Attach the principal to lend.".to_string()));
+        validate_apr_bps(apr_bps, "Offer");
+        require(duration > MIN_LISTING_DURATION_SEC, || {
+            ContractError::InvalidArgument(format!("Offer duration must be longer than {} seconds.", MIN_LISTING_DURATION_SEC))
+        });
+
+        let offer = LenderOffer {
+            id: self.next_lender_offer_id,
+            lender: env::predecessor_account_id(),
+            market: market.into(),
+            token_id,
+            principal: U128(principal),
+            apr_bps,
+            duration,
+            interest_model: interest_model.unwrap_or_default(),
+        };
+        offer.interest_model.validate();
+
+        self.internal_reserve_storage(&offer.lender, CREDIT_STORAGE_BYTES);
+        self.next_lender_offer_id += 1;
+        self.lender_offers.insert(&offer.id, &offer);
+
+        ContractEvent::OfferCreated(OfferData::from_lender_offer(&offer)).emit();
+        offer.id
+    }
+
+    pub fn cancel_lender_offer(&mut self, lender_offer_id: u64) {
+        let offer = self.internal_get_lender_offer(lender_offer_id);
+        require(env::predecessor_account_id() == offer.lender, || {
+            ContractError::Unauthorized("Only lender can cancel the offer.".to_string())
+        });
+
+        self.lender_offers.remove(&lender_offer_id);
+        self.internal_release_storage(&offer.lender, CREDIT_STORAGE_BYTES);
+        ContractEvent::OfferCancelled(OfferData::from_lender_offer(&offer)).emit();
+        Promise::new(offer.lender).transfer(offer.principal.0);
+    }
+
+    pub fn get_lender_offer(&self, lender_offer_id: u64) -> Option<LenderOffer> {
+        self.lender_offers.get(&lender_offer_id)
+    }
+
+    pub fn get_lender_offers(&self, from_index: Option<u64>, limit: Option<u64>) -> Vec<LenderOffer> {
+        paginate(self.lender_offers.values_as_vector(), from_index, limit)
+    }
+
+    /// Records the loan once the NFT is held, otherwise puts the offer back.
+    #[private]
+    pub fn resolve_lender_offer_lock(
+        &mut self,
+        token_id: TokenId,
+        owner_id: AccountId,
+        offer: LenderOffer,
+        request: AcceptLenderOfferRequest,
+    ) -> bool {
+        if !is_promise_success() {
+            env::log(format!("Failed to receive NFT {} from {}, lender offer {} restored.",
+                             token_id,
+                             owner_id,
+                             offer.id).as_bytes());
+            self.lender_offers.insert(&offer.id, &offer);
+            return false;
+        }
+        if !self.has_storage_available(&owner_id, LISTING_STORAGE_BYTES) {
+            env::log(format!("Storage balance no longer covers lender offer {} on {}, offer restored and NFT returned.",
+                             offer.id,
+                             token_id).as_bytes());
+            self.lender_offers.insert(&offer.id, &offer);
+            transfer_bundle_item(&token_id, &owner_id);
+            return false;
+        }
+
+        let listing = ListingRequest {
+            market: Some(offer.market),
+            principal: offer.principal,
+            apr_bps: offer.apr_bps,
+            apr: None,
+            duration: offer.duration,
+            extra: request.extra,
+            market_type: request.market_type,
+            title: request.title,
+            media: request.media,
+            auction: None,
+            interest_model: offer.interest_model,
+            installments: vec![],
+            currency: None,
+            liquidation: None,
+            grace_period: None,
+            late_fee: None,
+        };
+        let locked_token = LockedToken::from_listing(token_id, owner_id, listing);
+        self.internal_add_locked_token(&locked_token);
+        self.internal_release_storage(&offer.lender, CREDIT_STORAGE_BYTES);
+        self.internal_lock_token(locked_token, &offer.lender, offer.principal.0, offer.apr_bps);
+
+        true
+    }
+}
+
+impl Contract {
+    fn internal_get_lender_offer(&self, lender_offer_id: u64) -> LenderOffer {
+        self.lender_offers
+            .get(&lender_offer_id)
+            .unwrap_or_else(|| ContractError::NotFound(format!("lender offer {}", lender_offer_id)).panic())
+    }
+
+    /// The offer is taken out of the registry while the NFT transfer is in
+    /// flight, so it can't be cancelled or accepted twice meanwhile.
+    pub(crate) fn internal_accept_lender_offer(
+        &mut self,
+        token_id: TokenId,
+        owner_id: AccountId,
+        approval_id: String,
+        market: AccountId,
+        request: AcceptLenderOfferRequest,
+    ) {
+        let offer = self.internal_get_lender_offer(request.lender_offer_id);
+        require(offer.market == market, || {
+            ContractError::InvalidArgument(format!("Lender offer {} is for NFT contract {}.", offer.id, offer.market))
+        });
+        if let Some(offer_token_id) = &offer.token_id {
+            require(offer_token_id == &token_id, || {
+                ContractError::InvalidArgument(format!("Lender offer {} is for token {}.", offer.id, offer_token_id))
+            });
+        }
+        require(offer.lender != owner_id, || ContractError::Unauthorized("Lender can't accept own offer.".to_string()));
+
+        self.lender_offers.remove(&offer.id);
+
+        let token_unique_id = token_id.to_string() + ":" + &market;
+        marketplace::nft_transfer(ValidAccountId::try_from(env::current_account_id()).unwrap(),
+                                  token_id,
+                                  Some(approval_id),
+                                  None,
+                                  &market,
+                                  1,
+                                  GAS_FOR_NFT_TRANSFER)
+            .then(ext_self::resolve_lender_offer_lock(token_unique_id,
+                                                      owner_id,
+                                                      offer,
+                                                      request,
+                                                      &env::current_account_id(),
+                                                      0,
+                                                      GAS_FOR_RESOLVE_NFT_LOCK));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use near_sdk::PromiseResult;
+
+    fn setup_offer(token_id: Option<TokenId>) -> (Contract, LenderOffer) {
+        let mut contract = setup_contract();
+        register(&mut contract, ALICE, NEAR);
+        register(&mut contract, BOB, NEAR);
+        set_context(BOB, NEAR, 0);
+        let offer_id = contract.create_lender_offer(account(NFT_CONTRACT_ID), token_id, 1000, 3600, None);
+        let offer = contract.get_lender_offer(offer_id).unwrap();
+        (contract, offer)
+    }
+
+    /// `ALICE` approves NFT `nft_token_id` accepting `offer`, the transfer
+    /// resolves to `result`.
+    fn accept_offer(contract: &mut Contract, offer: &LenderOffer, nft_token_id: &str, result: PromiseResult) -> bool {
+        let msg = format!(r#"{{"lender_offer_id": {}, "title": "Offer"}}"#, offer.id);
+        set_nft_context(ALICE, 10);
+        contract.nft_on_approve(nft_token_id.to_string(), ALICE.to_string(), "0".to_string(), msg.clone());
+        assert!(contract.get_lender_offer(offer.id).is_none());
+
+        set_callback_context(10, result);
+        contract.resolve_lender_offer_lock(format!("{}:{}", nft_token_id, NFT_CONTRACT_ID),
+                                           ALICE.to_string(),
+                                           offer.clone(),
+                                           AcceptLenderOfferRequest::from_msg(&msg).unwrap())
+    }
+
+    #[test]
+    fn collection_offer_funds_a_loan_on_any_of_its_nfts() {
+        let (mut contract, offer) = setup_offer(None);
+        assert!(accept_offer(&mut contract, &offer, "7", PromiseResult::Successful(vec![])));
+        assert_eq!(get_transfers(), vec![(ALICE.to_string(), NEAR)]);
+
+        let token = contract.internal_get_token(&format!("7:{}", NFT_CONTRACT_ID));
+        assert_eq!(token.state, LockedTokenState::Locked);
+        assert_eq!((token.creditor_id(), token.borrowed_money, token.apr_bps, token.title.as_str()),
+                   (BOB.to_string(), NEAR, 1000, "Offer"));
+    }
+
+    #[test]
+    fn offer_is_restored_if_the_nft_doesnt_arrive() {
+        let (mut contract, offer) = setup_offer(None);
+        assert!(!accept_offer(&mut contract, &offer, "7", PromiseResult::Failed));
+        assert!(contract.get_lender_offer(offer.id).is_some());
+        assert!(contract.token_id_to_locked_token.get(&format!("7:{}", NFT_CONTRACT_ID)).is_none());
+    }
+
+    #[test]
+    fn cancelled_offer_refunds_the_principal() {
+        let (mut contract, offer) = setup_offer(None);
+        set_context(BOB, 0, 10);
+        contract.cancel_lender_offer(offer.id);
+        assert_eq!(get_transfers(), vec![(BOB.to_string(), NEAR)]);
+        assert!(contract.get_lender_offer(offer.id).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "Unauthorized")]
+    fn only_the_lender_cancels_an_offer() {
+        let (mut contract, offer) = setup_offer(None);
+        set_context(ALICE, 0, 10);
+        contract.cancel_lender_offer(offer.id);
+    }
+
+    #[test]
+    #[should_panic(expected = "InvalidArgument")]
+    fn token_offer_cant_be_accepted_with_another_nft() {
+        let (mut contract, offer) = setup_offer(Some("5".to_string()));
+        accept_offer(&mut contract, &offer, "6", PromiseResult::Successful(vec![]));
+    }
+}
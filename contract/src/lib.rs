@@ -1,9 +1,30 @@
 mod locked_token;
 mod cross_calls;
+mod listing_request;
+mod roles;
+mod auction;
+mod interest;
+mod repayment;
+mod extension;
+mod refinance;
+mod lender_offers;
+mod counter_offers;
+mod currency;
+mod bundle;
+mod fractions;
+mod pools;
+mod liquidation;
+mod treasury;
+mod storage;
+mod events;
+mod views;
+mod errors;
+#[cfg(test)]
+mod test_utils;
 
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
 use near_sdk::collections::{LookupMap, UnorderedMap, UnorderedSet};
-use near_sdk::json_types::ValidAccountId;
+use near_sdk::json_types::{ValidAccountId, U128};
 use near_sdk::{
     env, near_bindgen, AccountId, CryptoHash, PanicOnDefault, Promise,
 };
@@ -14,6 +35,23 @@ use near_contract_standards::non_fungible_token::{
 
 use crate::locked_token::*;
 use crate::cross_calls::*;
+use crate::listing_request::*;
+use crate::auction::*;
+use crate::interest::*;
+use crate::repayment::*;
+use crate::extension::*;
+use crate::refinance::*;
+use crate::lender_offers::*;
+use crate::counter_offers::*;
+use crate::currency::*;
+use crate::bundle::*;
+use crate::fractions::*;
+use crate::pools::*;
+use crate::liquidation::*;
+use crate::storage::*;
+use crate::events::*;
+use crate::views::*;
+use crate::errors::*;
 
 use std::str::FromStr;
 use std::time::Duration;
@@ -34,6 +72,83 @@ pub struct Contract {
     token_id_to_locked_token: LookupMap<TokenId, LockedToken>,
 
     nft_locker_by_token_id: LookupMap<TokenId, AccountId>,
+
+    accept_legacy_listing_msg: bool,
+
+    admin_ids: UnorderedSet<AccountId>,
+
+    keeper_ids: UnorderedSet<AccountId>,
+
+    loan_extensions: LookupMap<TokenId, LoanExtension>,
+
+    refinance_offers: LookupMap<TokenId, RefinanceOffer>,
+
+    lender_offers: UnorderedMap<u64, LenderOffer>,
+
+    next_lender_offer_id: u64,
+
+    counter_offers: LookupMap<TokenId, Vec<CounterOffer>>,
+
+    accepted_currencies: UnorderedSet<AccountId>,
+
+    pending_ft_payouts: LookupMap<(AccountId, AccountId), U128>,
+
+    bundles: LookupMap<TokenId, CollateralBundle>,
+
+    next_bundle_id: u64,
+
+    fractions: LookupMap<AccountId, Fractionalization>,
+
+    fraction_by_token: LookupMap<TokenId, AccountId>,
+
+    next_fraction_id: u64,
+
+    pools: UnorderedMap<u64, LendingPool>,
+
+    next_pool_id: u64,
+
+    pool_shares: LookupMap<(u64, AccountId), U128>,
+
+    pool_loans: LookupMap<TokenId, U128>,
+
+    pool_assets: LookupMap<TokenId, PoolAsset>,
+
+    liquidations: LookupMap<TokenId, Liquidation>,
+
+    default_grace_period: u64,
+
+    default_late_fee: Option<LateFee>,
+
+    treasury: UnorderedMap<String, U128>,
+
+    origination_fee_bps: u64,
+
+    interest_fee_bps: u64,
+
+    storage_accounts: LookupMap<AccountId, AccountStorage>,
+
+    listing_ids: UnorderedSet<TokenId>,
+
+    listings_per_market: LookupMap<AccountId, UnorderedSet<TokenId>>,
+
+    listings_per_state: LookupMap<LockedTokenState, UnorderedSet<TokenId>>,
+
+    state_version: u32,
+}
+
+/// Layout of `Contract` in the first deployed version, read by `migrate`.
+#[derive(BorshDeserialize)]
+#[cfg_attr(test, derive(BorshSerialize))]
+pub struct OldContract {
+    owner_id: AccountId,
+
+    tokens_stored_per_owner: UnorderedMap<AccountId, UnorderedSet<TokenId>>,
+
+    credit_tokens_per_creditor: UnorderedMap<AccountId, UnorderedSet<TokenId>>,
+
+    token_id_to_locked_token: LookupMap<TokenId, LegacyLockedToken>,
+
+    nft_locker_by_token_id: LookupMap<TokenId, AccountId>,
 }
 
 /// Helper structure to for keys of the persistent collections.
@@ -45,136 +160,221 @@ pub enum StorageKey {
     CreditNFTsPerOwner,
     CreditNFTsPerOwnerInner { account_id_hash: CryptoHash },
     LockerByTokenId,
-    TokeIdToLockedToken
+    TokeIdToLockedToken,
+    AdminIds,
+    KeeperIds,
+    LoanExtensions,
+    RefinanceOffers,
+    LenderOffers,
+    CounterOffers,
+    AcceptedCurrencies,
+    PendingFtPayouts,
+    Bundles,
+    Fractions,
+    FractionByToken,
+    Pools,
+    PoolShares,
+    PoolLoans,
+    PoolAssets,
+    Liquidations,
+    Treasury,
+    StorageAccounts,
+    Listings,
+    ListingsPerMarket,
+    ListingsPerMarketInner { account_id_hash: CryptoHash },
+    ListingsPerState,
+    ListingsPerStateInner { state: LockedTokenState },
 }
 
-const CONTRACT_NAME: &str = "contract.alevoro.testnet";
+/// Version of the `Contract` layout, `migrate` refuses to run on state that
+/// already has one.
+pub const STATE_VERSION: u32 = 1;
 
 #[near_bindgen]
 impl Contract {
     #[init]
-    pub fn new(owner_id: ValidAccountId) -> Self {
-        let this = Self {
-            owner_id: owner_id.into(),
-            tokens_stored_per_owner: UnorderedMap::new(StorageKey::NFTsPerOwner.try_to_vec().unwrap()),
-            nft_locker_by_token_id: LookupMap::new(StorageKey::LockerByTokenId.try_to_vec().unwrap()),
-            credit_tokens_per_creditor: UnorderedMap::new(StorageKey::CreditNFTsPerOwner.try_to_vec().unwrap()),
-            token_id_to_locked_token: LookupMap::new(StorageKey::TokeIdToLockedToken.try_to_vec().unwrap()),
-        };
+    pub fn new(
+        owner_id: ValidAccountId,
+        admin_ids: Option<Vec<ValidAccountId>>,
+        keeper_ids: Option<Vec<ValidAccountId>>,
+    ) -> Self {
+        let mut this = Self::internal_new(owner_id.into());
+
+        for admin_id in admin_ids.unwrap_or_default() {
+            this.admin_ids.insert(admin_id.as_ref());
+        }
+        for keeper_id in keeper_ids.unwrap_or_default() {
+            this.keeper_ids.insert(keeper_id.as_ref());
+        }
 
         this
     }
 
+    /// Pages through every listing, only those on sale unless `need_all`.
     pub fn get_all_locked_tokens(
         &self,
         need_all: bool,
-    ) -> Vec<LockedToken> {
-        let mut all_locked_tokens = vec![];
-        for account_id in self.tokens_stored_per_owner.keys_as_vector().iter() {
-            all_locked_tokens.append(&mut self.get_locked_tokens(account_id, need_all))
+        from_index: Option<u64>,
+        limit: Option<u64>,
+    ) -> Vec<LockedTokenView> {
+        if !need_all {
+            return self.get_listings_by_state(LockedTokenState::Sale, from_index, limit);
         }
-        all_locked_tokens
+        paginate(self.listing_ids.as_vector(), from_index, limit)
+            .iter()
+            .map(|x| LockedTokenView::from_token(self.internal_get_token(x)))
+            .collect()
     }
 
     pub fn get_debtors_tokens(
         &self,
         account_id: AccountId,
-    ) -> Vec<LockedToken> {
-        return self
-            .get_tokens_for_lent_money(&&account_id)
+        from_index: Option<u64>,
+        limit: Option<u64>,
+    ) -> Vec<LockedTokenView> {
+        paginate(self.get_tokens_for_lent_money(&&account_id).as_vector(), from_index, limit)
             .iter()
-            .map(|x| self.token_id_to_locked_token.get(&x).unwrap())
-            .collect();
+            .map(|x| LockedTokenView::from_token(self.internal_get_token(x)))
+            .collect()
     }
 
     pub fn get_locked_tokens(
         &self,
         account_id: AccountId,
         need_all: bool,
-    ) -> Vec<LockedToken> {
-        let mut tmp = vec![];
+        from_index: Option<u64>,
+        limit: Option<u64>,
+    ) -> Vec<LockedTokenView> {
         let tokens_owner = self.tokens_stored_per_owner.get(&account_id);
         let tokens = if let Some(tokens_owner) = tokens_owner {
             tokens_owner
         } else {
             return vec![];
         };
-        let keys = tokens.as_vector();
-        let start = 0;
-        let end = keys.len();
-        for i in start..end {
-            let cur_token: LockedToken =
-                self.token_id_to_locked_token.get(
-                    &keys.get(i).unwrap()
-                ).unwrap();
-            if need_all || cur_token.state == LockedTokenState::Sale {
-                tmp.push(cur_token);
-            }
-        }
-        tmp
+        paginate(tokens.as_vector(), from_index, limit)
+            .iter()
+            .map(|x| self.internal_get_token(x))
+            .filter(|cur_token| need_all || cur_token.state == LockedTokenState::Sale)
+            .map(LockedTokenView::from_token)
+            .collect()
     }
 
+    /// Called by the NFT contract on `nft_approve`. The NFT is pulled into the
+    /// contract and the listing is only recorded in `resolve_nft_lock` once
+    /// the transfer succeeded. A msg naming a `lender_offer_id` accepts that
+    /// lender offer, one naming a `bundle_id` adds the NFT to that draft
+    /// bundle and one naming a `pool_id` draws a loan from that pool instead
+    /// of creating a listing.
     #[payable]
     pub fn nft_on_approve(&mut self, token_id: TokenId, owner_id: AccountId, approval_id: String, msg: String) {
-        assert_eq!(env::signer_account_id(), owner_id);
-        let initial_storage_usage = env::storage_usage() as i128;
-
-        let params: Vec<&str> = msg.split("!#@").collect();
-        let (market,
-            borrowed_money,
-            apr,
-            borrow_duration,
-            extra,
-            market_type,
-            title,
-            media) = (params[0], params[1], params[2], params[3], params[4], params[5], params[6], params[7]);
-
-        assert!(borrow_duration.parse::<u64>().unwrap() > 60);
-        assert!(apr.parse::<u64>().unwrap() > 0);
-
-        marketplace::nft_transfer(ValidAccountId::try_from(CONTRACT_NAME).unwrap(),
+        require(env::signer_account_id() == owner_id, || {
+            ContractError::Unauthorized("Only the NFT owner can list it.".to_string())
+        });
+
+        let market = env::predecessor_account_id();
+        let token_unique_id = token_id.to_string() + ":" + &market;
+        if self.token_id_to_locked_token.contains_key(&token_unique_id) {
+            ContractError::Conflict(format!("Token {} is already listed.", token_unique_id)).panic();
+        }
+        self.assert_storage_available(&owner_id, LISTING_STORAGE_BYTES);
+
+        if let Some(request) = AcceptLenderOfferRequest::from_msg(&msg) {
+            return self.internal_accept_lender_offer(token_id, owner_id, approval_id, market, request);
+        }
+        if let Some(request) = AddToBundleRequest::from_msg(&msg) {
+            return self.internal_add_to_bundle(token_id, owner_id, approval_id, market, request);
+        }
+        if let Some(request) = PoolLoanRequest::from_msg(&msg) {
+            return self.internal_draw_pool_loan(token_id, owner_id, approval_id, market, request);
+        }
+
+        let listing = ListingRequest::from_msg(&msg, self.accept_legacy_listing_msg);
+        listing.assert_market(&market);
+        if let Some(currency) = &listing.currency {
+            self.assert_accepted_currency(currency);
+        }
+
+        marketplace::nft_transfer(ValidAccountId::try_from(env::current_account_id()).unwrap(),
                                   token_id.to_string(),
                                   Some(approval_id),
                                   None,
-                                  &market.clone(),
+                                  &market,
                                   1,
-                                  10_000_000_000_000);
-
-        let mut locked_tokens_ids = self.get_tokens_stored_per_owner(&&owner_id);
-        let token_unique_id = token_id.to_string() + ":" + market;
-        let locked_token = LockedToken {
-            token_id: token_unique_id.clone().to_string(),
-            owner_id: owner_id.clone(),
-            duration: borrow_duration.parse::<u64>().unwrap(),
-            borrowed_money: borrowed_money.to_string(),
-            apr: apr.parse::<u64>().unwrap(),
-            creditor: None,
-            start_time: None,
-            extra: extra.to_string(),
-            market_type: market_type.to_string(),
-            title: title.to_string(),
-            media: media.to_string(),
-            state: LockedTokenState::Sale,
-        };
+                                  GAS_FOR_NFT_TRANSFER)
+            .then(ext_self::resolve_nft_lock(token_unique_id,
+                                             owner_id,
+                                             listing,
+                                             &env::current_account_id(),
+                                             0,
+                                             GAS_FOR_RESOLVE_NFT_LOCK));
+    }
 
-        locked_tokens_ids.insert(&token_unique_id);
+    #[private]
+    pub fn resolve_nft_lock(&mut self, token_id: TokenId, owner_id: AccountId, listing: ListingRequest) -> bool {
+        if !is_promise_success() {
+            env::log(format!("Failed to receive NFT {} from {}, listing discarded.", token_id, owner_id).as_bytes());
+            return false;
+        }
+        if !self.has_storage_available(&owner_id, LISTING_STORAGE_BYTES) {
+            env::log(format!("Storage balance of {} no longer covers listing {}, NFT returned.", owner_id, token_id).as_bytes());
+            transfer_bundle_item(&token_id, &owner_id);
+            return false;
+        }
 
-        self.token_id_to_locked_token.insert(&token_unique_id.to_string(), &locked_token);
-        self.tokens_stored_per_owner.insert(&owner_id, &locked_tokens_ids);
-        self.nft_locker_by_token_id.insert(&token_unique_id.to_string(), &owner_id);
+        self.internal_add_locked_token(&LockedToken::from_listing(token_id, owner_id, listing));
 
-        // let storage_usage = max(0,
-        //                         env::storage_usage() as i128 - initial_storage_usage as i128)
-        //     as StorageUsage;
-        //
-        // refund_deposit(storage_usage);
+        true
     }
 
+    /// Records a listing, its storage is charged to the owner.
+    fn internal_add_locked_token(&mut self, locked_token: &LockedToken) {
+        let initial_storage_usage = env::storage_usage();
+
+        let mut locked_tokens_ids = self.get_tokens_stored_per_owner(&&locked_token.owner_id);
+        locked_tokens_ids.insert(&locked_token.token_id);
+
+        self.token_id_to_locked_token.insert(&locked_token.token_id, locked_token);
+        self.tokens_stored_per_owner.insert(&locked_token.owner_id, &locked_tokens_ids);
+        self.nft_locker_by_token_id.insert(&locked_token.token_id, &locked_token.owner_id);
+        self.internal_index_listing(locked_token);
+
+        self.internal_charge_storage(&locked_token.owner_id, initial_storage_usage);
+        if locked_token.auction.is_some() {
+            self.internal_reserve_storage(&locked_token.owner_id, LOAN_STORAGE_BYTES);
+        }
+
+        ContractEvent::ListingCreated(ListingData::from_token(locked_token)).emit();
+    }
+
+    pub(crate) fn internal_get_token(&self, token_id: &TokenId) -> LockedToken {
+        self.token_id_to_locked_token
+            .get(token_id)
+            .unwrap_or_else(|| ContractError::TokenNotFound(token_id.clone()).panic())
+    }
+
+    /// Stores changes of a token that must already be recorded.
+    fn internal_replace_token(&mut self, token: &LockedToken) {
+        if self.token_id_to_locked_token.insert(&token.token_id, token).is_none() {
+            ContractError::Internal(format!("Token {} was stored without being listed.", token.token_id)).panic();
+        }
+    }
+
+    /// Allows the legacy "!#@"-delimited `nft_approve` msg while listings
+    /// created by older frontends are migrated.
+    pub fn set_accept_legacy_listing_msg(&mut self, accept: bool) {
+        self.assert_owner();
+        self.accept_legacy_listing_msg = accept;
+    }
+
+    pub fn get_accept_legacy_listing_msg(&self) -> bool {
+        self.accept_legacy_listing_msg
+    }
 
     #[payable]
     pub fn transfer_nft_back(&mut self, token_id: TokenId) {
         let owner_id = &env::predecessor_account_id();
-        self.change_status_to_some_returning(&owner_id, &owner_id, token_id, LockedTokenState::Return);
+        self.assert_no_shares_sold(&token_id);
+        self.change_status_to_some_returning(&owner_id, &owner_id, token_id, LockedTokenState::Return, 0);
     }
 
     #[payable]
@@ -183,215 +383,246 @@ impl Contract {
         init_owner: &&AccountId,
         return_owner: &&AccountId,
         token_id: TokenId,
-        action: LockedTokenState
+        action: LockedTokenState,
+        repayment: u128
     ) {
-        let initial_storage_usage = env::storage_usage() as i128;
-
         let locked_tokens_ids = self.get_tokens_stored_per_owner(init_owner);
         let token_exists_and_valid = locked_tokens_ids
             .iter()
-            .find(|x| x.to_string() == token_id);
+            .find(|x| *x == token_id);
 
         if let Some(some_token_id) = token_exists_and_valid {
-            let token = self.token_id_to_locked_token.get(&some_token_id).unwrap();
+            let token = self.internal_get_token(&some_token_id);
 
-            assert_eq!(token.owner_id.to_string(), init_owner.to_string());
+            require(&token.owner_id == *init_owner, || {
+                ContractError::Unauthorized(format!("Token {} isn't owned by {}.", token_id, init_owner))
+            });
 
             match action {
                 LockedTokenState::Return => {
-                    assert_eq!(token.state, LockedTokenState::Sale);
-                    assert_eq!(init_owner, return_owner);
+                    require_state(&token, LockedTokenState::Sale);
+                    require(init_owner == return_owner, || {
+                        ContractError::Unauthorized("Only owner can take back the listing.".to_string())
+                    });
 
                     let mut changed_state_token = token.clone();
-                    changed_state_token.state = LockedTokenState::Return;
-
-                    assert!(self.
-                        token_id_to_locked_token
-                        .insert(&some_token_id, &changed_state_token)
-                        .is_some()
-                    );
+                    self.internal_set_state(&mut changed_state_token, LockedTokenState::Return);
+                    if let Some(auction) = changed_state_token.auction.as_mut() {
+                        if let Some(bidder) = auction.cancel() {
+                            self.internal_release_storage(&bidder, CREDIT_STORAGE_BYTES);
+                        }
+                        self.internal_release_storage(&token.owner_id, LOAN_STORAGE_BYTES);
+                    }
+
+                    self.internal_replace_token(&changed_state_token);
+                    ContractEvent::ListingCancelled(TokenData::from_token(&token)).emit();
                 }
                 LockedTokenState::TransferToCreditor => {
-                    assert_eq!(token.state, LockedTokenState::Locked);
-                    assert_eq!(return_owner.to_string(), token.clone().creditor.unwrap());
+                    require_state(&token, LockedTokenState::Locked);
+                    require(*return_owner == &token.creditor_id(), || {
+                        ContractError::Unauthorized("Only creditor can take the overdue collateral.".to_string())
+                    });
 
                     let mut changed_state_token = token.clone();
-                    changed_state_token.state = LockedTokenState::TransferToCreditor;
+                    self.internal_set_state(&mut changed_state_token, LockedTokenState::TransferToCreditor);
 
-                    assert!(self.
-                        token_id_to_locked_token
-                        .insert(&some_token_id, &changed_state_token)
-                        .is_some()
-                    );
+                    self.internal_replace_token(&changed_state_token);
                 }
                 LockedTokenState::TransferToBorrower => {
-                    assert_eq!(token.state, LockedTokenState::Locked);
-                    assert_eq!(return_owner.to_string(), token.owner_id);
+                    require_state(&token, LockedTokenState::Locked);
+                    require(*return_owner == &token.owner_id, || {
+                        ContractError::Unauthorized("Only borrower can take back the repaid collateral.".to_string())
+                    });
 
                     let mut changed_state_token = token.clone();
-                    changed_state_token.state = LockedTokenState::TransferToBorrower;
+                    self.internal_set_state(&mut changed_state_token, LockedTokenState::TransferToBorrower);
 
-                    assert!(self.
-                        token_id_to_locked_token
-                        .insert(&some_token_id, &changed_state_token)
-                        .is_some()
-                    );
+                    self.internal_replace_token(&changed_state_token);
                 }
-                _ => env::panic("Unreachable state!".to_string().as_bytes())
+                _ => ContractError::Internal(format!("Can't return a token for state {}.", action)).panic()
             }
 
-            // let storage_usage = max(0,
-            //                         env::storage_usage() as i128 - initial_storage_usage as i128)
-            //     as StorageUsage;
-            //
-            // env::log(format!("Was {}. Now: {}.", initial_storage_usage, env::storage_usage()).as_bytes());
-            //
-            // refund_deposit(storage_usage);
-
-            //
-            // if !is_repaid.clone() && !is_delayed.clone() {
-            //     refund_deposit(required_storage_in_bytes);
-            // }
+            self.internal_drop_loan_extension(&some_token_id);
+            self.internal_remove_refinance_offer(&token);
+            self.internal_refund_counter_offers(&some_token_id);
+            self.internal_transfer_nft(&token, return_owner, repayment);
         } else {
-            env::panic(format!("Can't find token with Id: {} in contract .", token_id).as_bytes());
+            ContractError::TokenNotFound(token_id).panic();
         }
     }
 
     #[payable]
     pub fn transfer_deposit_for_nft(&mut self, token_id: TokenId) {
-        let initial_storage_usage = env::storage_usage() as i128;
+        let lender_id = env::predecessor_account_id();
+        self.internal_fund_listing(&lender_id, token_id, env::attached_deposit(), None);
+    }
+
+    /// Funds a fixed-terms `Sale` listing with exactly its principal, paid in
+    /// `currency` (`None` for NEAR).
+    fn internal_fund_listing(&mut self, lender_id: &AccountId, token_id: TokenId, deposit: u128, currency: Option<AccountId>) {
+        self.assert_storage_available(lender_id, CREDIT_STORAGE_BYTES);
 
-        let lender_id = &env::predecessor_account_id();
         let token_owner_id = self
             .nft_locker_by_token_id
-            .get(&token_id).unwrap();
-
-        assert_ne!(lender_id.to_string(), token_owner_id.to_string());
+            .get(&token_id)
+            .unwrap_or_else(|| ContractError::TokenNotFound(token_id.clone()).panic());
 
-        env::log(format!("Seller: {}", token_owner_id).as_bytes());
-        env::log(format!("TokeID: {}", token_id).as_bytes());
+        require(lender_id != &token_owner_id, || ContractError::Unauthorized("Owner can't fund own listing.".to_string()));
 
         let owner_locked_tokens_ids = self.get_tokens_stored_per_owner(&&token_owner_id);
-        let mut tokens_for_lent_money_ids = self.get_tokens_for_lent_money(&&lender_id);
 
         let token_exists_and_valid = owner_locked_tokens_ids
             .iter()
-            .find(|x| x.to_string() == token_id);
+            .find(|x| *x == token_id);
 
         if let Some(some_token_id) = token_exists_and_valid {
-            let token = self
-                .token_id_to_locked_token
-                .get(&some_token_id)
-                .unwrap();
-
-            let deposit = env::attached_deposit();
-            env::log(format!("State: {}", token.state).as_bytes());
-
-            if token.state == LockedTokenState::Sale {
-                let expected_amount_to_lend = u128::from_str(&token.borrowed_money)
-                    .expect("Failed to parse expected amount to lend.");
-                assert_eq!(deposit, expected_amount_to_lend);
-
-                let mut accept_deal_locked_token = token.clone();
-                accept_deal_locked_token.state = LockedTokenState::Locked;
-                accept_deal_locked_token.creditor = Some(AccountId::from(lender_id));
-                accept_deal_locked_token.start_time = Some(env::block_timestamp());
-
-                assert!(self.
-                    token_id_to_locked_token
-                    .insert(&some_token_id, &accept_deal_locked_token)
-                    .is_some()
-                );
-
-                tokens_for_lent_money_ids.insert(&some_token_id);
-                self.credit_tokens_per_creditor.insert(lender_id, &tokens_for_lent_money_ids);
-
-                Promise::new(token_owner_id).transfer(deposit);
-            } else {
-                env::panic("Token has already been bought or owner canceled the order.".as_bytes())
+            let token = self.internal_get_token(&some_token_id);
+
+            assert_token_currency(&token, &currency);
+            require_state(&token, LockedTokenState::Sale);
+            if self.is_fractionalized(&token_id) {
+                ContractError::Unsupported(format!("Token {} is funded by selling shares, use buy_shares.", token_id)).panic();
+            }
+            if token.auction.is_some() {
+                ContractError::Unsupported(format!("Token {} is listed on auction, use place_bid.", token_id)).panic();
             }
+
+            require(deposit == token.borrowed_money, || {
+                ContractError::WrongDeposit(format!("Attach exactly the listed principal {}.", token.borrowed_money))
+            });
+
+            let apr_bps = token.apr_bps;
+            self.internal_lock_token(token, lender_id, deposit, apr_bps);
         } else {
-            env::panic(format!("Can't find token with Id: {} in contract.", token_id).as_bytes());
+            ContractError::TokenNotFound(token_id).panic();
+        }
+    }
+
+    /// Turns a `Sale` listing into a `Locked` loan funded by `lender_id` and
+    /// pays `principal` out to the borrower, less the origination fee.
+    fn internal_lock_token(&mut self, mut token: LockedToken, lender_id: &AccountId, principal: u128, apr_bps: u64) {
+        self.internal_refund_counter_offers(&token.token_id);
+        self.internal_start_loan(&mut token, lender_id, principal, apr_bps);
+        ContractEvent::LoanFunded(LoanData::from_token(&token)).emit();
+
+        let fee = self.internal_collect_fee(&token.currency, principal, self.origination_fee_bps);
+        self.internal_pay(&token.currency, &token.owner_id, principal - fee);
+    }
+
+    /// Starts new loan terms for `token` with `lender_id` as creditor. The
+    /// owner pays for the grown listing, the lender for the credit record.
+    fn internal_start_loan(&mut self, token: &mut LockedToken, lender_id: &AccountId, principal: u128, apr_bps: u64) {
+        let initial_storage_usage = env::storage_usage();
+
+        self.internal_set_state(token, LockedTokenState::Locked);
+        token.creditor = Some(lender_id.clone());
+        token.start_time = Some(env::block_timestamp());
+        token.borrowed_money = principal;
+        token.apr_bps = apr_bps;
+        token.auction = None;
+        token.outstanding_principal = U128(principal);
+        token.accrued_interest = U128(token.interest_model.interest(principal, apr_bps, 0));
+        token.accrued_at = token.start_time;
+        if token.grace_period.is_none() {
+            token.grace_period = Some(self.default_grace_period);
+        }
+        if token.late_fee.is_none() {
+            token.late_fee = self.default_late_fee.clone();
         }
 
-        // let storage_usage = max(0,
-        //                         env::storage_usage() as i128 - initial_storage_usage as i128)
-        //     as StorageUsage;
-        //
-        // refund_deposit(storage_usage);
+        self.internal_replace_token(token);
+        self.internal_charge_storage(&token.owner_id, initial_storage_usage);
+
+        let initial_storage_usage = env::storage_usage();
+        let mut tokens_for_lent_money_ids = self.get_tokens_for_lent_money(&lender_id);
+        tokens_for_lent_money_ids.insert(&token.token_id);
+        self.credit_tokens_per_creditor.insert(lender_id, &tokens_for_lent_money_ids);
+        self.internal_charge_storage(lender_id, initial_storage_usage);
+    }
+
+    fn internal_remove_credit_token(&mut self, creditor: &AccountId, token_id: &TokenId) {
+        let initial_storage_usage = env::storage_usage();
+        let mut creditor_lent_money_tokens_ids = self
+            .get_tokens_for_lent_money(&creditor);
+
+        if !creditor_lent_money_tokens_ids.remove(token_id) {
+            ContractError::Internal(format!("Token {} isn't among the credits of {}.", token_id, creditor)).panic();
+        }
+        if creditor_lent_money_tokens_ids.is_empty() {
+            self.credit_tokens_per_creditor.remove(creditor);
+        } else {
+            self.credit_tokens_per_creditor.insert(creditor, &creditor_lent_money_tokens_ids);
+        }
+        self.internal_charge_storage(creditor, initial_storage_usage);
     }
 
     fn get_tokens_for_lent_money(&self, lender_id: &&String) -> UnorderedSet<TokenId> {
-        let tokens_for_borrowed_money_ids = self.credit_tokens_per_creditor
-            .get(&lender_id).unwrap_or_else(|| {
+        self.credit_tokens_per_creditor.get(lender_id).unwrap_or_else(|| {
             UnorderedSet::new(
                 StorageKey::CreditNFTsPerOwnerInner {
-                    account_id_hash: hash_account_id(&lender_id),
+                    account_id_hash: hash_account_id(lender_id),
                 }
                     .try_to_vec()
                     .unwrap(),
             )
-        });
-        tokens_for_borrowed_money_ids
+        })
     }
 
     fn get_tokens_stored_per_owner(&self, account_id: &&String) -> UnorderedSet<TokenId> {
-        let locked_tokens_ids = self.tokens_stored_per_owner.get(account_id).unwrap_or_else(|| {
+        self.tokens_stored_per_owner.get(account_id).unwrap_or_else(|| {
             UnorderedSet::new(
                 StorageKey::NFTsPerOwnerInner {
-                    account_id_hash: hash_account_id(&account_id),
+                    account_id_hash: hash_account_id(account_id),
                 }
                     .try_to_vec()
                     .unwrap(),
             )
-        });
-        locked_tokens_ids
+        })
     }
 
     #[payable]
     pub fn repay_loan(&mut self, token_id: TokenId) {
         let deposit = env::attached_deposit();
-        let owner_id = &env::predecessor_account_id();
+        let owner_id = env::predecessor_account_id();
+
+        let excess = self.internal_repay_loan(&owner_id, token_id, deposit, None);
+        if excess > 0 {
+            Promise::new(owner_id).transfer(excess);
+        }
+    }
 
+    /// Starts returning the NFT against full repayment in `currency` (`None`
+    /// for NEAR). Returns the part of `deposit` exceeding the amount due.
+    fn internal_repay_loan(&mut self, owner_id: &AccountId, token_id: TokenId, deposit: u128, currency: Option<AccountId>) -> u128 {
         let contract_locked_tokens_ids = self.get_tokens_stored_per_owner(&owner_id);
 
         let token_exists_and_valid = contract_locked_tokens_ids
             .iter()
-            .find(|x| x.to_string() == token_id);
+            .find(|x| *x == token_id);
 
         if let Some(some_token_id) = token_exists_and_valid {
-            let token = self
-                .token_id_to_locked_token
-                .get(&some_token_id)
-                .unwrap();
-
-            env::log(format!("Token state: {}", token.state).as_bytes());
-            assert_eq!(&token.owner_id, owner_id);
-
-            if token.state == LockedTokenState::Locked {
-                assert!(!self.check_is_token_delayed(token.clone()));
-
-                let mut borrowed_money = u128::from_str(&token.borrowed_money)
-                    .expect("Failed to parse borrowed amount");
-                borrowed_money += borrowed_money * u128::from(token.apr) / 100;
-
-                assert_eq!(deposit, borrowed_money);
-
-                self.change_status_to_some_returning(&owner_id,
-                                                     &owner_id,
-                                                     token_id,
-                                                     LockedTokenState::TransferToBorrower);
-
-                if let Some(creditor) = token.creditor {
-                    Promise::new(creditor).transfer(deposit);
-                } else {
-                    env::panic("Creditor does not exist".as_bytes())
-                }
-            } else {
-                env::panic("Token isn't locked".as_bytes())
-            }
+            let token = self.internal_get_token(&some_token_id);
+
+            require(&token.owner_id == owner_id, || ContractError::Unauthorized("Only borrower can repay the loan.".to_string()));
+            assert_token_currency(&token, &currency);
+            require_state(&token, LockedTokenState::Locked);
+            require(!self.check_is_token_delayed(token.clone()), || ContractError::Overdue(token_id.clone()));
+
+            let total_due = self.internal_repayment_quote(&token).total_due.0;
+            require(deposit >= total_due, || {
+                ContractError::WrongDeposit(format!("Attached deposit {} doesn't cover the amount due {}.", deposit, total_due))
+            });
+            require(token.creditor.is_some(), || ContractError::NoCreditor(token_id.clone()));
+
+            self.change_status_to_some_returning(&owner_id,
+                                                 &owner_id,
+                                                 token_id,
+                                                 LockedTokenState::TransferToBorrower,
+                                                 total_due);
+
+            deposit - total_due
         } else {
-            env::panic(format!("Can't find token with Id: {} in contract .", token_id).as_bytes());
+            ContractError::TokenNotFound(token_id).panic();
         }
     }
 
@@ -403,119 +634,389 @@ impl Contract {
 
         let token_exists_and_valid = locked_tokens_ids
             .iter()
-            .find(|x| x.to_string() == token_id);
+            .find(|x| *x == token_id);
 
         if let Some(some_token_id) = token_exists_and_valid {
-            let token = self
-                .token_id_to_locked_token
-                .get(&some_token_id)
-                .unwrap();
-
-            assert_eq!(token.creditor.clone().unwrap().to_string(), creditor_id.to_string());
-            assert_eq!(token.state, LockedTokenState::Locked);
-
-            if self.check_is_token_delayed(token.clone()) {
-                self.change_status_to_some_returning(&&token.owner_id,
-                                                     &&token.creditor.unwrap(),
-                                                     token_id,
-                                                     LockedTokenState::TransferToCreditor);
-                env::log(format!("Successfully transferred NFT from {} to {} by creditor request.",
-                                 token.owner_id,
-                                 env::predecessor_account_id()).as_bytes());
+            let token = self.internal_get_token(&some_token_id);
+
+            require(&token.creditor_id() == creditor_id, || {
+                ContractError::Unauthorized("Only creditor can take the overdue collateral.".to_string())
+            });
+            require_state(&token, LockedTokenState::Locked);
+            require(self.check_is_token_delayed(token.clone()), || ContractError::NotOverdue(token_id.clone()));
+
+            ContractEvent::LoanDefaulted(TokenData::from_token(&token)).emit();
+            if token.liquidation.is_some() {
+                return self.internal_start_liquidation(&token);
+            }
+            self.change_status_to_some_returning(&&token.owner_id,
+                                                 &&token.creditor_id(),
+                                                 token_id,
+                                                 LockedTokenState::TransferToCreditor,
+                                                 0);
+        } else {
+            ContractError::TokenNotFound(token_id).panic();
+        }
+    }
+
+    /// Transfers every NFT of the collateral out of the contract, the record
+    /// is settled in `resolve_nft_return`. `repayment` is held until the
+    /// transfers succeed and then paid out to the creditor.
+    fn internal_transfer_nft(&self, token: &LockedToken, receiver_id: &AccountId, repayment: u128) -> Promise {
+        token
+            .collateral()
+            .iter()
+            .map(|item_id| transfer_bundle_item(item_id, receiver_id))
+            .reduce(|transfers, transfer| transfers.and(transfer))
+            .unwrap_or_else(|| ContractError::Internal(format!("Token {} has no collateral.", token.token_id)).panic())
+            .then(ext_self::resolve_nft_return(token.token_id.clone(),
+                                               token.state.clone(),
+                                               U128(repayment),
+                                               &env::current_account_id(),
+                                               0,
+                                               GAS_FOR_RESOLVE_NFT_RETURN))
+    }
+
+    /// Removes the token from the contract once the NFTs left it, otherwise
+    /// restores `previous_state` and refunds the held repayment to the borrower.
+    ///
+    /// If only part of a bundle was transferred the loan is settled anyway,
+    /// the rest of the bundle stays in the transfer state for
+    /// `retry_collateral_release`.
+    #[private]
+    pub fn resolve_nft_return(&mut self, token_id: TokenId, previous_state: LockedTokenState, repayment: U128) -> bool {
+        let mut token = self.internal_get_token(&token_id);
+
+        let collateral = token.collateral();
+        let remaining: Vec<TokenId> = collateral
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| !is_promise_success_at(*index as u64))
+            .map(|(_, item_id)| item_id.clone())
+            .collect();
+
+        if remaining.len() < collateral.len() {
+            let receiver_id = if token.state == LockedTokenState::TransferToCreditor {
+                token.creditor_id()
             } else {
-                env::panic(format!("There is still time for borrower to return money.")
-                    .as_bytes());
+                token.owner_id.clone()
+            };
+            if repayment.0 > 0 {
+                ContractEvent::LoanRepaid(RepaymentData {
+                    token: TokenData::from_token(&token),
+                    amount: repayment,
+                    outstanding_principal: U128(0),
+                    currency: token.currency.clone(),
+                }).emit();
             }
+            ContractEvent::CollateralReleased(ReleaseData {
+                token: TokenData::from_token(&token),
+                receiver_id,
+                price: None,
+            }).emit();
+
+            if repayment.0 > 0 {
+                let creditor = token.creditor_id();
+                let interest = repayment.0.saturating_sub(token.outstanding_principal.0);
+                let fee = self.internal_collect_fee(&token.currency, interest, self.interest_fee_bps);
+                self.internal_pay_creditor(&token, &creditor, repayment.0 - fee);
+            }
+            if remaining.is_empty() {
+                self.internal_remove_locked_token(&token_id);
+                return true;
+            }
+            env::log(format!("Failed to transfer {} of bundle {}, retry with retry_collateral_release.",
+                             remaining.join(", "),
+                             token_id).as_bytes());
+            token.bundle = remaining;
+            self.token_id_to_locked_token.insert(&token_id, &token);
+            false
         } else {
-            env::panic(format!("Can't find token with Id: {} in contract .", token_id)
-                .as_bytes());
+            env::log(format!("Failed to transfer NFT {}, state restored to {}.", token_id, previous_state).as_bytes());
+            if repayment.0 > 0 {
+                self.internal_pay(&token.currency, &token.owner_id, repayment.0);
+            }
+            self.internal_set_state(&mut token, previous_state);
+            self.token_id_to_locked_token.insert(&token_id, &token);
+            false
         }
     }
 
-    #[payable]
-    pub fn remove_transferred_token_from_locked_tokens(&mut self, token_id: TokenId) {
-        let storage = &env::predecessor_account_id();
-        assert_eq!(storage.to_string(), CONTRACT_NAME);
+    /// Re-sends the collateral of a token stuck in a transfer state, e.g. the
+    /// rest of a partly released bundle or a record migrated mid-transfer.
+    /// The record is only removed once `resolve_nft_return` sees the NFTs
+    /// leave. Callable by anyone, the NFTs only go to their receiver.
+    pub fn retry_collateral_release(&mut self, token_id: TokenId) {
+        let token = self.internal_get_token(&token_id);
+
+        let receiver_id = match token.state {
+            LockedTokenState::Return | LockedTokenState::TransferToBorrower => token.owner_id.clone(),
+            LockedTokenState::TransferToCreditor => token.creditor_id(),
+            _ => ContractError::WrongPhase(format!("Token {} isn't being transferred out, it is in state {}.",
+                                                   token_id,
+                                                   token.state)).panic(),
+        };
+        self.internal_transfer_nft(&token, &receiver_id, 0);
+    }
 
+    /// Deletes all records of a token and releases their storage to the owner
+    /// and the creditor.
+    fn internal_remove_locked_token(&mut self, token_id: &TokenId) -> LockedToken {
         let init_owner = self
             .nft_locker_by_token_id
-            .get(&token_id)
-            .expect("No such token stored in contract.");
+            .get(token_id)
+            .unwrap_or_else(|| ContractError::TokenNotFound(token_id.clone()).panic());
+
+        let creditor = self.internal_get_token(token_id).creditor;
+        if let Some(creditor) = &creditor {
+            self.internal_remove_credit_token(creditor, token_id);
+        }
 
+        let initial_storage_usage = env::storage_usage();
         let mut locked_tokens_ids = self
             .get_tokens_stored_per_owner(&&init_owner.clone());
 
-        let token_exists_and_valid = locked_tokens_ids
-            .iter()
-            .find(|x| x.to_string() == token_id);
-
-        if let Some(some_token_id) = token_exists_and_valid {
-            let token = self
-                .token_id_to_locked_token
-                .get(&some_token_id)
-                .unwrap();
-
-            assert!(token.creditor.is_some() &&
-                (token.state == LockedTokenState::TransferToCreditor ||
-                    token.state == LockedTokenState::TransferToBorrower) ||
-                token.creditor.is_none() && token.state == LockedTokenState::Return);
-
-            assert!(locked_tokens_ids.remove(&token_id));
+        if !locked_tokens_ids.remove(token_id) {
+            ContractError::Internal(format!("Can't find token with Id: {} in locked tokens of last owner.", token_id)).panic();
+        }
+        if locked_tokens_ids.is_empty() {
+            self.tokens_stored_per_owner.remove(&init_owner);
+        } else {
             self.tokens_stored_per_owner.insert(&init_owner, &locked_tokens_ids);
+        }
 
-            if token.creditor.is_some() {
-                let mut creditor_lent_money_tokens_ids = self
-                    .get_tokens_for_lent_money(&&token.clone().creditor.unwrap());
+        let token = self
+            .token_id_to_locked_token
+            .remove(token_id)
+            .unwrap_or_else(|| ContractError::TokenNotFound(token_id.clone()).panic());
 
-                assert!(creditor_lent_money_tokens_ids.remove(&token_id));
-                self
-                    .credit_tokens_per_creditor
-                    .insert(&token.creditor.unwrap(), &creditor_lent_money_tokens_ids);
-            }
+        self.nft_locker_by_token_id.remove(token_id);
+        self.internal_unindex_listing(&token);
+        self.internal_charge_storage(&init_owner, initial_storage_usage);
+        self.internal_settle_fraction(token_id, &init_owner);
 
-            assert!(self.nft_locker_by_token_id.remove(&token_id).is_some());
-            assert!(self.token_id_to_locked_token.remove(&token_id).is_some());
-
-            env::log(format!("Fully removed token: {} from contract.", token_id).as_bytes());
-        } else {
-            env::panic(format!("Can't find token with Id: {} in locked tokens of last owner.", token_id).as_bytes());
-        }
+        token
     }
 
+    /// Whether the loan is in default, i.e. past due for longer than its
+    /// grace period.
     fn check_is_token_delayed(&self, token: LockedToken) -> bool {
-        let now = Duration::from_nanos(env::block_timestamp());
-        let deal_time = Duration::from_nanos(token.start_time.unwrap());
-
-        let diff = now - deal_time;
-        let sec_diff = diff.as_secs();
+        self.internal_seconds_past_due(&token)
+            .is_some_and(|seconds_past_due| seconds_past_due >= token.grace_period.unwrap_or(0))
+    }
 
-        return sec_diff >= token.duration;
+    fn get_loan_elapsed_sec(&self, token: &LockedToken) -> u64 {
+        seconds_since(token.loan_start())
     }
 
 
+    /// Upgrades the state of the first deployed version, the only state
+    /// layout without a `state_version`. Panics on any other state, so it
+    /// can't run twice.
     #[init(ignore_state)]
     #[private]
     pub fn migrate() -> Self {
+        let state = env::storage_read(b"STATE")
+            .unwrap_or_else(|| ContractError::Internal("Contract is not initialized.".to_string()).panic());
+        let old = OldContract::try_from_slice(&state).unwrap_or_else(|_| {
+            match Self::try_from_slice(&state) {
+                Ok(this) => ContractError::Conflict(format!("Contract state is already at version {}.", this.state_version)),
+                Err(_) => ContractError::Internal("Failed to read contract state.".to_string()),
+            }.panic()
+        });
 
+        let token_ids: Vec<TokenId> = old
+            .tokens_stored_per_owner
+            .values_as_vector()
+            .iter()
+            .flat_map(|locked_tokens_ids| locked_tokens_ids.to_vec())
+            .collect();
+        let mut this = Self {
+            tokens_stored_per_owner: old.tokens_stored_per_owner,
+            credit_tokens_per_creditor: old.credit_tokens_per_creditor,
+            nft_locker_by_token_id: old.nft_locker_by_token_id,
+            ..Self::internal_new(old.owner_id)
+        };
+        // Both layouts store tokens under the same prefix, the legacy record
+        // has to be taken out before the new one is written.
+        let mut legacy_tokens = old.token_id_to_locked_token;
+        for token_id in token_ids {
+            let legacy_token = legacy_tokens
+                .remove(&token_id)
+                .unwrap_or_else(|| ContractError::TokenNotFound(token_id.clone()).panic());
+            let token = LockedToken::from_legacy(legacy_token);
+            this.token_id_to_locked_token.insert(&token_id, &token);
+            this.internal_index_listing(&token);
+        }
+
+        this
+    }
+}
+
+impl Contract {
+    /// Empty state of the current layout, shared by `new` and `migrate`.
+    fn internal_new(owner_id: AccountId) -> Self {
         Self {
-            owner_id: CONTRACT_NAME.to_string(),
-            tokens_stored_per_owner: UnorderedMap::new(
-                StorageKey::NFTsPerOwner
-                .try_to_vec()
-                .unwrap()),
-            credit_tokens_per_creditor: UnorderedMap::new(
-                StorageKey::CreditNFTsPerOwner
-                    .try_to_vec()
-                    .unwrap()),
-            nft_locker_by_token_id: LookupMap::new(
-                StorageKey::LockerByTokenId
-                    .try_to_vec()
-                    .unwrap()),
-            token_id_to_locked_token:  LookupMap::new(
-                StorageKey::TokeIdToLockedToken
-                    .try_to_vec()
-                    .unwrap())
+            owner_id,
+            tokens_stored_per_owner: UnorderedMap::new(StorageKey::NFTsPerOwner.try_to_vec().unwrap()),
+            nft_locker_by_token_id: LookupMap::new(StorageKey::LockerByTokenId.try_to_vec().unwrap()),
+            credit_tokens_per_creditor: UnorderedMap::new(StorageKey::CreditNFTsPerOwner.try_to_vec().unwrap()),
+            token_id_to_locked_token: LookupMap::new(StorageKey::TokeIdToLockedToken.try_to_vec().unwrap()),
+            accept_legacy_listing_msg: false,
+            admin_ids: UnorderedSet::new(StorageKey::AdminIds.try_to_vec().unwrap()),
+            keeper_ids: UnorderedSet::new(StorageKey::KeeperIds.try_to_vec().unwrap()),
+            loan_extensions: LookupMap::new(StorageKey::LoanExtensions.try_to_vec().unwrap()),
+            refinance_offers: LookupMap::new(StorageKey::RefinanceOffers.try_to_vec().unwrap()),
+            lender_offers: UnorderedMap::new(StorageKey::LenderOffers.try_to_vec().unwrap()),
+            next_lender_offer_id: 0,
+            counter_offers: LookupMap::new(StorageKey::CounterOffers.try_to_vec().unwrap()),
+            accepted_currencies: UnorderedSet::new(StorageKey::AcceptedCurrencies.try_to_vec().unwrap()),
+            pending_ft_payouts: LookupMap::new(StorageKey::PendingFtPayouts.try_to_vec().unwrap()),
+            bundles: LookupMap::new(StorageKey::Bundles.try_to_vec().unwrap()),
+            next_bundle_id: 0,
+            fractions: LookupMap::new(StorageKey::Fractions.try_to_vec().unwrap()),
+            fraction_by_token: LookupMap::new(StorageKey::FractionByToken.try_to_vec().unwrap()),
+            next_fraction_id: 0,
+            pools: UnorderedMap::new(StorageKey::Pools.try_to_vec().unwrap()),
+            next_pool_id: 0,
+            pool_shares: LookupMap::new(StorageKey::PoolShares.try_to_vec().unwrap()),
+            pool_loans: LookupMap::new(StorageKey::PoolLoans.try_to_vec().unwrap()),
+            pool_assets: LookupMap::new(StorageKey::PoolAssets.try_to_vec().unwrap()),
+            liquidations: LookupMap::new(StorageKey::Liquidations.try_to_vec().unwrap()),
+            default_grace_period: 0,
+            default_late_fee: None,
+            treasury: UnorderedMap::new(StorageKey::Treasury.try_to_vec().unwrap()),
+            origination_fee_bps: 0,
+            interest_fee_bps: 0,
+            storage_accounts: LookupMap::new(StorageKey::StorageAccounts.try_to_vec().unwrap()),
+            listing_ids: UnorderedSet::new(StorageKey::Listings.try_to_vec().unwrap()),
+            listings_per_market: LookupMap::new(StorageKey::ListingsPerMarket.try_to_vec().unwrap()),
+            listings_per_state: LookupMap::new(StorageKey::ListingsPerState.try_to_vec().unwrap()),
+            state_version: STATE_VERSION,
         }
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use near_sdk::PromiseResult;
+
+    const LISTED_ID: &str = "1:nft.near";
+    const LOAN_ID: &str = "2:nft.near";
+    const LOAN_START: u64 = 5_000_000_000;
+
+    fn legacy_token(token_id: &str, state: LockedTokenState, creditor: Option<AccountId>, start_time: Option<u64>) -> LegacyLockedToken {
+        LegacyLockedToken {
+            token_id: token_id.to_string(),
+            owner_id: ALICE.to_string(),
+            duration: 3_600,
+            borrowed_money: NEAR.to_string(),
+            apr: 10,
+            creditor,
+            start_time,
+            extra: String::new(),
+            market_type: "nft".to_string(),
+            title: token_id.to_string(),
+            media: String::new(),
+            state,
+        }
+    }
+
+    fn token_set(prefix: StorageKey, token_ids: &[&str]) -> UnorderedSet<TokenId> {
+        let mut set = UnorderedSet::new(prefix.try_to_vec().unwrap());
+        for token_id in token_ids {
+            set.insert(&token_id.to_string());
+        }
+        set
+    }
+
+    /// Writes the state of the first deployed version, with a listing of
+    /// `ALICE` on sale and a loan `BOB` funded.
+    fn write_baseline_state() {
+        set_context(CONTRACT_ID, 0, 0);
+        let mut old = OldContract {
+            owner_id: OWNER.to_string(),
+            tokens_stored_per_owner: UnorderedMap::new(StorageKey::NFTsPerOwner.try_to_vec().unwrap()),
+            credit_tokens_per_creditor: UnorderedMap::new(StorageKey::CreditNFTsPerOwner.try_to_vec().unwrap()),
+            token_id_to_locked_token: LookupMap::new(StorageKey::TokeIdToLockedToken.try_to_vec().unwrap()),
+            nft_locker_by_token_id: LookupMap::new(StorageKey::LockerByTokenId.try_to_vec().unwrap()),
+        };
+        for token in [legacy_token(LISTED_ID, LockedTokenState::Sale, None, None),
+                      legacy_token(LOAN_ID, LockedTokenState::Locked, Some(BOB.to_string()), Some(LOAN_START))] {
+            old.nft_locker_by_token_id.insert(&token.token_id, &token.owner_id);
+            old.token_id_to_locked_token.insert(&token.token_id, &token);
+        }
+        let alice_hash = hash_account_id(&ALICE.to_string());
+        old.tokens_stored_per_owner.insert(&ALICE.to_string(),
+                                           &token_set(StorageKey::NFTsPerOwnerInner { account_id_hash: alice_hash },
+                                                      &[LISTED_ID, LOAN_ID]));
+        let bob_hash = hash_account_id(&BOB.to_string());
+        old.credit_tokens_per_creditor.insert(&BOB.to_string(),
+                                              &token_set(StorageKey::CreditNFTsPerOwnerInner { account_id_hash: bob_hash },
+                                                         &[LOAN_ID]));
+        env::state_write(&old);
+    }
+
+    #[test]
+    fn migrate_converts_baseline_records() {
+        write_baseline_state();
+        let contract = Contract::migrate();
+        assert_eq!(contract.owner_id, OWNER);
+        assert_eq!(contract.state_version, STATE_VERSION);
+
+        let listed = contract.internal_get_token(&LISTED_ID.to_string());
+        assert_eq!((listed.apr_bps, listed.interest_model.clone()), (1_000, InterestModel::FlatFee));
+        assert_eq!((listed.outstanding_principal.0, listed.accrued_interest.0, listed.accrued_at), (NEAR, 0, None));
+
+        let loan = contract.internal_get_token(&LOAN_ID.to_string());
+        assert_eq!(loan.creditor_id(), BOB);
+        assert_eq!((loan.accrued_interest.0, loan.accrued_at), (NEAR / 10, Some(LOAN_START)));
+        set_context(ALICE, 0, 10);
+        assert_eq!(contract.get_repayment_quote(LOAN_ID.to_string()).total_due.0, NEAR + NEAR / 10);
+
+        assert_eq!(contract.get_locked_tokens(ALICE.to_string(), true, None, None).len(), 2);
+        assert_eq!(contract.get_debtors_tokens(BOB.to_string(), None, None).len(), 1);
+        let on_sale = contract.get_all_locked_tokens(false, None, None);
+        assert_eq!(on_sale.len(), 1);
+        assert_eq!(on_sale[0].token_id, LISTED_ID);
+    }
+
+    #[test]
+    fn stuck_transfer_is_retried_until_the_nft_leaves() {
+        write_baseline_state();
+        let mut contract = Contract::migrate();
+        let mut loan = contract.internal_get_token(&LOAN_ID.to_string());
+        contract.internal_set_state(&mut loan, LockedTokenState::TransferToBorrower);
+        contract.internal_replace_token(&loan);
+
+        for delivered in [false, true] {
+            set_context(CHARLIE, 0, 10);
+            contract.retry_collateral_release(LOAN_ID.to_string());
+            let result = if delivered { PromiseResult::Successful(vec![]) } else { PromiseResult::Failed };
+            set_callback_context(10, result);
+            assert_eq!(contract.resolve_nft_return(LOAN_ID.to_string(), LockedTokenState::TransferToBorrower, U128(0)),
+                       delivered);
+            assert_eq!(contract.token_id_to_locked_token.get(&LOAN_ID.to_string()).is_none(), delivered);
+        }
+        assert!(contract.get_debtors_tokens(BOB.to_string(), None, None).is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "WrongPhase")]
+    fn listings_on_sale_arent_released() {
+        write_baseline_state();
+        let mut contract = Contract::migrate();
+        contract.retry_collateral_release(LISTED_ID.to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "Conflict")]
+    fn migrate_runs_once() {
+        write_baseline_state();
+        let contract = Contract::migrate();
+        env::state_write(&contract);
+        Contract::migrate();
+    }
+}
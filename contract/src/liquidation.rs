@@ -0,0 +1,362 @@
+use crate::*;
+use near_sdk::json_types::{U128, U64};
+
+/// Shortest liquidation auction a listing can ask for.
+pub const MIN_LIQUIDATION_DURATION_SEC: u64 = 3600;
+/// Longest liquidation auction a listing can ask for.
+pub const MAX_LIQUIDATION_DURATION_SEC: u64 = 30 * 24 * 60 * 60;
+
+/// How defaulted collateral is auctioned off.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub enum LiquidationKind {
+    /// Ascending bids starting at the debt, the best bid wins at the deadline.
+    English,
+    /// The price falls linearly from `start_price` to the debt, the first bid
+    /// at the current price wins.
+    Dutch { start_price: U128 },
+}
+
+/// Liquidation parameters of a `ListingRequest`.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct LiquidationRequest {
+    pub kind: LiquidationKind,
+    /// Seconds the auction runs after the default.
+    pub duration: u64,
+}
+
+impl LiquidationRequest {
+    pub fn validate(&self) {
+        if self.duration < MIN_LIQUIDATION_DURATION_SEC || self.duration > MAX_LIQUIDATION_DURATION_SEC {
+            ContractError::InvalidArgument(format!("Liquidation must run between {} and {} seconds.",
+                                                   MIN_LIQUIDATION_DURATION_SEC,
+                                                   MAX_LIQUIDATION_DURATION_SEC)).panic();
+        }
+        if let LiquidationKind::Dutch { start_price } = &self.kind {
+            if start_price.0 == 0 {
+                ContractError::InvalidArgument("Dutch liquidation start_price must be positive.".to_string()).panic();
+            }
+        }
+    }
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct LiquidationBid {
+    pub bidder: AccountId,
+    pub amount: U128,
+}
+
+/// Running liquidation of a defaulted loan. `debt` is frozen at the default
+/// and is the reserve price. The best bid is held by the contract.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Liquidation {
+    pub kind: LiquidationKind,
+    pub debt: U128,
+    pub started_at: U64,
+    pub deadline: U64,
+    pub best_bid: Option<LiquidationBid>,
+}
+
+impl Liquidation {
+    pub fn is_finished(&self) -> bool {
+        env::block_timestamp() >= self.deadline.0
+    }
+
+    /// Lowest acceptable bid at the current block.
+    pub fn current_price(&self) -> u128 {
+        match &self.kind {
+            LiquidationKind::English => self
+                .best_bid
+                .as_ref()
+                .map_or(self.debt.0, |bid| bid.amount.0 + 1),
+            LiquidationKind::Dutch { start_price } => {
+                let start_price = std::cmp::max(start_price.0, self.debt.0);
+                let elapsed = std::cmp::min(env::block_timestamp(), self.deadline.0) - self.started_at.0;
+                start_price - mul_div(start_price - self.debt.0,
+                                      elapsed as u128,
+                                      (self.deadline.0 - self.started_at.0) as u128)
+            }
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Bids the attached deposit on defaulted collateral. A Dutch auction is
+    /// won right away, the excess over the current price is refunded.
+    #[payable]
+    pub fn place_liquidation_bid(&mut self, token_id: TokenId) {
+        let bidder = env::predecessor_account_id();
+        let deposit = env::attached_deposit();
+        let mut liquidation = self.internal_get_liquidation(&token_id);
+        require(!liquidation.is_finished(), || {
+            ContractError::WrongPhase(format!("Liquidation of {} has ended, call settle_liquidation.", token_id))
+        });
+
+        let price = liquidation.current_price();
+        require(deposit >= price, || {
+            ContractError::WrongDeposit(format!("Attached deposit {} is below the current price {}.", deposit, price))
+        });
+
+        match liquidation.kind {
+            LiquidationKind::English => {
+                if let Some(outbid) = liquidation.best_bid.take() {
+                    Promise::new(outbid.bidder).transfer(outbid.amount.0);
+                }
+                self.internal_emit_liquidation_bid(&token_id, &bidder, deposit);
+                liquidation.best_bid = Some(LiquidationBid { bidder, amount: U128(deposit) });
+                self.liquidations.insert(&token_id, &liquidation);
+            }
+            LiquidationKind::Dutch { .. } => {
+                if deposit > price {
+                    Promise::new(bidder.clone()).transfer(deposit - price);
+                }
+                self.internal_emit_liquidation_bid(&token_id, &bidder, price);
+                self.liquidations.remove(&token_id);
+                self.internal_release_liquidated_collateral(&token_id, &bidder, price, liquidation.debt);
+            }
+        }
+    }
+
+    /// Ends a liquidation after its deadline. Without a winning bid the
+    /// creditor receives the NFT.
+    pub fn settle_liquidation(&mut self, token_id: TokenId) {
+        let liquidation = self.internal_get_liquidation(&token_id);
+        require(liquidation.is_finished(), || ContractError::WrongPhase(format!("Liquidation of {} is still running.", token_id)));
+        self.liquidations.remove(&token_id);
+
+        if let Some(bid) = liquidation.best_bid {
+            self.internal_release_liquidated_collateral(&token_id, &bid.bidder, bid.amount.0, liquidation.debt);
+        } else {
+            let token = self.internal_get_token(&token_id);
+            let creditor = token.creditor_id();
+            env::log(format!("No bid covered the debt of {}, transferring NFT to {}.", token_id, creditor).as_bytes());
+            self.change_status_to_some_returning(&&token.owner_id,
+                                                 &&creditor,
+                                                 token_id,
+                                                 LockedTokenState::TransferToCreditor,
+                                                 0);
+        }
+    }
+
+    /// Pays the debt to the creditor, less the interest fee, and the surplus
+    /// to the borrower once the NFT reached the winner, otherwise refunds the
+    /// winner. A failed
+    /// liquidation can be restarted by the creditor.
+    #[private]
+    pub fn resolve_liquidation(&mut self, token_id: TokenId, winner: AccountId, price: U128, debt: U128) -> bool {
+        let mut token = self.internal_get_token(&token_id);
+
+        if !is_promise_success() {
+            env::log(format!("Failed to transfer {} to {}, bid refunded.", token_id, winner).as_bytes());
+            Promise::new(winner).transfer(price.0);
+            self.internal_set_state(&mut token, LockedTokenState::Locked);
+            self.token_id_to_locked_token.insert(&token_id, &token);
+            return false;
+        }
+
+        ContractEvent::CollateralReleased(ReleaseData {
+            token: TokenData::from_token(&token),
+            receiver_id: winner,
+            price: Some(price),
+        }).emit();
+
+        let creditor = token.creditor_id();
+        let to_creditor = std::cmp::min(price.0, debt.0);
+        let interest = to_creditor.saturating_sub(token.outstanding_principal.0);
+        let fee = self.internal_collect_fee(&token.currency, interest, self.interest_fee_bps);
+        self.internal_pay_creditor(&token, &creditor, to_creditor - fee);
+        if price.0 > to_creditor {
+            Promise::new(token.owner_id.clone()).transfer(price.0 - to_creditor);
+        }
+        self.internal_remove_locked_token(&token_id);
+        true
+    }
+
+    pub fn get_liquidation(&self, token_id: TokenId) -> Option<Liquidation> {
+        self.liquidations.get(&token_id)
+    }
+
+    /// Lowest bid `place_liquidation_bid` accepts at the current block.
+    pub fn get_liquidation_price(&self, token_id: TokenId) -> U128 {
+        U128(self.internal_get_liquidation(&token_id).current_price())
+    }
+}
+
+impl Contract {
+    fn internal_get_liquidation(&self, token_id: &TokenId) -> Liquidation {
+        self.liquidations
+            .get(token_id)
+            .unwrap_or_else(|| ContractError::NotFound(format!("liquidation of {}", token_id)).panic())
+    }
+
+    /// Panics while the token's collateral is being liquidated, its loan terms
+    /// can't change any more.
+    pub(crate) fn assert_not_liquidating(&self, token_id: &TokenId) {
+        require(!self.liquidations.contains_key(token_id), || {
+            ContractError::WrongPhase(format!("Token {} is being liquidated.", token_id))
+        });
+    }
+
+    /// Puts the collateral of a defaulted loan up for auction, the debt due at
+    /// this block is the reserve price.
+    pub(crate) fn internal_start_liquidation(&mut self, token: &LockedToken) {
+        let request = token.liquidation.clone().unwrap_or_else(|| {
+            ContractError::Unsupported(format!("Token {} has no liquidation terms.", token.token_id)).panic()
+        });
+        require(!self.liquidations.contains_key(&token.token_id), || {
+            ContractError::Conflict(format!("Token {} is already being liquidated.", token.token_id))
+        });
+
+        let debt = self.internal_repayment_quote(token).total_due;
+        let started_at = env::block_timestamp();
+        let deadline = started_at + Duration::from_secs(request.duration).as_nanos() as u64;
+        self.liquidations.insert(&token.token_id, &Liquidation {
+            kind: request.kind,
+            debt,
+            started_at: U64(started_at),
+            deadline: U64(deadline),
+            best_bid: None,
+        });
+
+        self.internal_drop_loan_extension(&token.token_id);
+        self.internal_remove_refinance_offer(token);
+
+        ContractEvent::LiquidationStarted(LiquidationData {
+            token: TokenData::from_token(token),
+            debt,
+            deadline: U64(deadline),
+        }).emit();
+    }
+
+    fn internal_emit_liquidation_bid(&self, token_id: &TokenId, bidder: &AccountId, amount: u128) {
+        let token = self.internal_get_token(token_id);
+        ContractEvent::LiquidationBid(LiquidationBidData {
+            token: TokenData::from_token(&token),
+            bidder_id: bidder.clone(),
+            amount: U128(amount),
+        }).emit();
+    }
+
+    fn internal_release_liquidated_collateral(&mut self, token_id: &TokenId, winner: &AccountId, price: u128, debt: U128) {
+        let mut token = self.internal_get_token(token_id);
+        self.internal_set_state(&mut token, LockedTokenState::TransferToCreditor);
+        self.token_id_to_locked_token.insert(token_id, &token);
+
+        transfer_bundle_item(token_id, winner)
+            .then(ext_self::resolve_liquidation(token_id.clone(),
+                                                winner.clone(),
+                                                U128(price),
+                                                debt,
+                                                &env::current_account_id(),
+                                                0,
+                                                GAS_FOR_RESOLVE_NFT_RETURN));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use near_sdk::PromiseResult;
+
+    const DAVE: &str = "dave.near";
+    const ENGLISH_MSG: &str = r#"{"principal": "1000000000000000000000000", "apr_bps": 1000, "duration": 3600, "title": "Listing",
+        "interest_model": "FlatFee", "liquidation": {"kind": "English", "duration": 3600}}"#;
+    const DUTCH_MSG: &str = r#"{"principal": "1000000000000000000000000", "apr_bps": 1000, "duration": 3600, "title": "Listing",
+        "interest_model": "FlatFee", "liquidation": {"kind": {"Dutch": {"start_price": "2000000000000000000000000"}}, "duration": 3600}}"#;
+    const DEBT: u128 = NEAR * 11 / 10;
+
+    /// Funds a listing of ALICE by BOB and lets BOB start the liquidation once
+    /// the loan defaulted.
+    fn setup_liquidation(msg: &str) -> (Contract, TokenId) {
+        let mut contract = setup_contract();
+        register(&mut contract, ALICE, NEAR);
+        register(&mut contract, BOB, NEAR);
+        let token_id = list_nft(&mut contract, ALICE, "1", msg);
+        fund_listing(&mut contract, BOB, &token_id, NEAR, 0);
+
+        set_context(BOB, 0, 3600);
+        contract.check_transfer_overdue_nft_to_creditor(token_id.clone());
+        (contract, token_id)
+    }
+
+    fn bid(contract: &mut Contract, token_id: &TokenId, bidder: &str, amount: u128, timestamp_sec: u64) {
+        set_context(bidder, amount, timestamp_sec);
+        contract.place_liquidation_bid(token_id.clone());
+    }
+
+    fn assert_nft_sent_to(receiver_id: &str) {
+        let transfer = get_function_calls().into_iter().find(|(_, method_name, _)| method_name == "nft_transfer").unwrap();
+        assert_eq!((transfer.0.as_str(), transfer.2["receiver_id"].as_str()), (NFT_CONTRACT_ID, Some(receiver_id)));
+    }
+
+    #[test]
+    fn english_liquidation_pays_the_debt_and_the_surplus() {
+        let (mut contract, token_id) = setup_liquidation(ENGLISH_MSG);
+        set_context(OWNER, 0, 3600);
+        contract.set_protocol_fees(0, 1000);
+        assert_eq!(contract.get_liquidation_price(token_id.clone()).0, DEBT);
+
+        bid(&mut contract, &token_id, CHARLIE, DEBT, 4000);
+        bid(&mut contract, &token_id, DAVE, NEAR * 3 / 2, 5000);
+        assert_eq!(get_transfers(), vec![(CHARLIE.to_string(), DEBT)]);
+
+        set_context(CHARLIE, 0, 7200);
+        contract.settle_liquidation(token_id.clone());
+        assert_nft_sent_to(DAVE);
+        assert_eq!(contract.internal_get_token(&token_id).state, LockedTokenState::TransferToCreditor);
+
+        set_callback_context(7200, PromiseResult::Successful(vec![]));
+        assert!(contract.resolve_liquidation(token_id.clone(), DAVE.to_string(), U128(NEAR * 3 / 2), U128(DEBT)));
+        assert_eq!(get_transfers(), vec![(BOB.to_string(), DEBT - NEAR / 100), (ALICE.to_string(), NEAR * 4 / 10)]);
+        assert_eq!(contract.get_treasury_balance(None).0, NEAR / 100);
+        assert!(contract.token_id_to_locked_token.get(&token_id).is_none());
+    }
+
+    #[test]
+    fn creditor_gets_the_nft_without_bids() {
+        let (mut contract, token_id) = setup_liquidation(ENGLISH_MSG);
+        set_context(CHARLIE, 0, 7200);
+        contract.settle_liquidation(token_id.clone());
+
+        assert!(contract.get_liquidation(token_id.clone()).is_none());
+        assert_eq!(contract.internal_get_token(&token_id).state, LockedTokenState::TransferToCreditor);
+        assert_nft_sent_to(BOB);
+    }
+
+    #[test]
+    fn dutch_liquidation_is_won_at_the_falling_price() {
+        let (mut contract, token_id) = setup_liquidation(DUTCH_MSG);
+        set_context(CHARLIE, 0, 5400);
+        assert_eq!(contract.get_liquidation_price(token_id.clone()).0, NEAR * 155 / 100);
+
+        bid(&mut contract, &token_id, CHARLIE, NEAR * 16 / 10, 5400);
+        assert_eq!(get_transfers(), vec![(CHARLIE.to_string(), NEAR / 20)]);
+        assert_nft_sent_to(CHARLIE);
+        assert!(contract.get_liquidation(token_id.clone()).is_none());
+
+        set_callback_context(5400, PromiseResult::Failed);
+        assert!(!contract.resolve_liquidation(token_id.clone(), CHARLIE.to_string(), U128(NEAR * 155 / 100), U128(DEBT)));
+        assert_eq!(get_transfers(), vec![(CHARLIE.to_string(), NEAR * 155 / 100)]);
+        assert_eq!(contract.internal_get_token(&token_id).state, LockedTokenState::Locked);
+    }
+
+    #[test]
+    #[should_panic(expected = "WrongDeposit")]
+    fn bids_below_the_debt_are_rejected() {
+        let (mut contract, token_id) = setup_liquidation(ENGLISH_MSG);
+        bid(&mut contract, &token_id, CHARLIE, NEAR, 4000);
+    }
+
+    #[test]
+    #[should_panic(expected = "WrongPhase")]
+    fn liquidation_settles_after_its_deadline() {
+        let (mut contract, token_id) = setup_liquidation(ENGLISH_MSG);
+        set_context(CHARLIE, 0, 7000);
+        contract.settle_liquidation(token_id);
+    }
+}
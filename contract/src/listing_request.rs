@@ -0,0 +1,167 @@
+use crate::*;
+use near_sdk::json_types::U128;
+use near_sdk::serde_json;
+
+/// Separator of the legacy positional `nft_approve` msg.
+pub const LEGACY_MSG_SEPARATOR: &str = "!#@";
+const LEGACY_MSG_FIELDS: usize = 8;
+
+/// Listings shorter than this are rejected.
+pub const MIN_LISTING_DURATION_SEC: u64 = 60;
+
+/// Listing parameters passed by the borrower as the `msg` of `nft_approve`.
+///
+/// Fields added later must be optional (`Option` or `#[serde(default)]`),
+/// so that msgs built by older frontends keep deserializing.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ListingRequest {
+    /// NFT contract the token belongs to. The market is always taken from the
+    /// calling NFT contract, if set this only has to match it.
+    #[serde(default)]
+    pub market: Option<AccountId>,
+    pub principal: U128,
+    /// Annual rate in basis points.
+    #[serde(default)]
+    pub apr_bps: u64,
+    /// Annual rate in percent, as sent by frontends built before `apr_bps`.
+    /// Converted into `apr_bps` on parsing.
+    #[serde(default, skip_serializing)]
+    pub apr: Option<u64>,
+    pub duration: u64,
+    #[serde(default)]
+    pub extra: String,
+    #[serde(default)]
+    pub market_type: String,
+    pub title: String,
+    #[serde(default)]
+    pub media: String,
+    /// Lets lenders bid on the listing instead of accepting it as is.
+    #[serde(default)]
+    pub auction: Option<AuctionRequest>,
+    /// Defaults to `InterestModel::Simple`.
+    #[serde(default)]
+    pub interest_model: InterestModel,
+    /// Optional repayment schedule, the loan defaults on a missed installment.
+    #[serde(default)]
+    pub installments: Vec<Installment>,
+    /// Whitelisted NEP-141 contract to lend in, NEAR if not set.
+    #[serde(default)]
+    pub currency: Option<AccountId>,
+    /// Auctions the NFT on default instead of handing it to the creditor.
+    #[serde(default)]
+    pub liquidation: Option<LiquidationRequest>,
+    /// Seconds past due the loan can still be repaid, the global default if
+    /// not set.
+    #[serde(default)]
+    pub grace_period: Option<u64>,
+    /// Charged while repaying within the grace period, the global default if
+    /// not set.
+    #[serde(default)]
+    pub late_fee: Option<LateFee>,
+}
+
+impl ListingRequest {
+    /// Parses and validates `msg`. The legacy "!#@"-delimited format is only
+    /// accepted while `accept_legacy` is set.
+    pub fn from_msg(msg: &str, accept_legacy: bool) -> Self {
+        let request = if msg.trim_start().starts_with('{') {
+            let mut request = serde_json::from_str::<ListingRequest>(msg).unwrap_or_else(|e| {
+                ContractError::InvalidMsg(format!("Invalid listing msg: {}", e)).panic()
+            });
+            if let Some(apr) = request.apr.take() {
+                if request.apr_bps != 0 {
+                    ContractError::InvalidMsg("Listing msg can't set both apr and apr_bps.".to_string()).panic();
+                }
+                request.apr_bps = apr.checked_mul(BPS_PER_PERCENT).unwrap_or_else(|| {
+                    ContractError::InvalidArgument(format!("Listing apr {} is too large.", apr)).panic()
+                });
+            }
+            request
+        } else if accept_legacy {
+            Self::from_legacy_msg(msg)
+        } else {
+            ContractError::InvalidMsg("Listing msg must be a JSON object, legacy format is disabled.".to_string()).panic()
+        };
+        request.validate();
+        request
+    }
+
+    fn from_legacy_msg(msg: &str) -> Self {
+        let params: Vec<&str> = msg.split(LEGACY_MSG_SEPARATOR).collect();
+        if params.len() != LEGACY_MSG_FIELDS {
+            ContractError::InvalidMsg(format!("Legacy listing msg must have {} fields separated by \"{}\", got {}.",
+                                              LEGACY_MSG_FIELDS,
+                                              LEGACY_MSG_SEPARATOR,
+                                              params.len())).panic();
+        }
+
+        Self {
+            market: Some(params[0].to_string()),
+            principal: U128(parse_legacy_field(params[1], "principal")),
+            apr_bps: parse_legacy_field::<u64>(params[2], "apr") * BPS_PER_PERCENT,
+            apr: None,
+            duration: parse_legacy_field(params[3], "duration"),
+            extra: params[4].to_string(),
+            market_type: params[5].to_string(),
+            title: params[6].to_string(),
+            media: params[7].to_string(),
+            auction: None,
+            interest_model: InterestModel::FlatFee,
+            installments: vec![],
+            currency: None,
+            liquidation: None,
+            grace_period: None,
+            late_fee: None,
+        }
+    }
+
+    /// Panics if the listing names a market other than the calling NFT contract.
+    pub fn assert_market(&self, market: &AccountId) {
+        if let Some(listing_market) = &self.market {
+            if listing_market != market {
+                ContractError::InvalidArgument(format!("Listing market {} doesn't match the calling NFT contract {}.",
+                                                       listing_market,
+                                                       market)).panic();
+            }
+        }
+    }
+
+    pub(crate) fn validate(&self) {
+        if self.principal.0 == 0 {
+            ContractError::InvalidArgument("Listing principal must be positive.".to_string()).panic();
+        }
+        validate_apr_bps(self.apr_bps, "Listing");
+        if self.duration <= MIN_LISTING_DURATION_SEC {
+            ContractError::InvalidArgument(format!("Listing duration must be longer than {} seconds, got {}.",
+                                                   MIN_LISTING_DURATION_SEC,
+                                                   self.duration)).panic();
+        }
+        if let Some(auction) = &self.auction {
+            auction.validate();
+            if self.currency.is_some() {
+                ContractError::Unsupported("Auction listings can only be denominated in NEAR.".to_string()).panic();
+            }
+        }
+        if let Some(liquidation) = &self.liquidation {
+            liquidation.validate();
+            if self.currency.is_some() {
+                ContractError::Unsupported("Liquidation auctions can only be denominated in NEAR.".to_string()).panic();
+            }
+        }
+        if let Some(grace_period) = self.grace_period {
+            validate_grace_period(grace_period);
+        }
+        if let Some(late_fee) = &self.late_fee {
+            late_fee.validate();
+        }
+        self.interest_model.validate();
+        validate_installments(&self.installments, self.duration);
+    }
+}
+
+fn parse_legacy_field<T: FromStr>(value: &str, name: &str) -> T {
+    value.parse::<T>().unwrap_or_else(|_| {
+        ContractError::InvalidMsg(format!("Legacy listing msg has invalid {}: \"{}\".", name, value)).panic()
+    })
+}
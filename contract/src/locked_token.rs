@@ -1,4 +1,5 @@
 use crate::*;
+use near_sdk::json_types::{U128, U64};
 use std::fmt::{Display, Formatter};
 pub use serde::{Serialize, Deserialize};
 
@@ -23,9 +24,183 @@ impl Display for LockedTokenState {
     }
 }
 
-#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
-#[serde(crate = "near_sdk::serde")]
+/// Stored listing or loan, views return it as `LockedTokenView`.
+#[derive(BorshDeserialize, BorshSerialize, Clone)]
 pub struct LockedToken {
+    pub token_id: TokenId,
+    pub owner_id: AccountId,
+    pub duration: u64,
+    pub borrowed_money: u128,
+    pub apr_bps: u64,
+    pub creditor: Option<AccountId>,
+    pub start_time: Option<u64>,
+    pub extra: String,
+    pub market_type: String,
+    pub title: String,
+    pub media: String,
+    pub state: LockedTokenState,
+    pub auction: Option<Auction>,
+    pub interest_model: InterestModel,
+    /// Principal not yet paid back, `borrowed_money` keeps the original amount.
+    pub outstanding_principal: U128,
+    /// Interest accrued up to `accrued_at` and not yet paid.
+    pub accrued_interest: U128,
+    pub accrued_at: Option<u64>,
+    pub installments: Vec<Installment>,
+    /// NEP-141 contract the loan is denominated in, `None` for NEAR.
+    pub currency: Option<AccountId>,
+    /// `token_id:market` ids of the NFTs securing a bundle loan, empty if
+    /// the loan is secured by the `token_id` NFT alone.
+    pub bundle: Vec<TokenId>,
+    /// Liquidation auction run on default, the creditor takes the NFT if
+    /// not set.
+    pub liquidation: Option<LiquidationRequest>,
+    /// Seconds past due before the collateral can be seized.
+    pub grace_period: Option<u64>,
+    pub late_fee: Option<LateFee>,
+}
+
+/// Splits a contract-wide `token_id:market` id into the NFT token id and the
+/// NFT contract it belongs to.
+pub fn split_token_unique_id(token_unique_id: &str) -> (TokenId, AccountId) {
+    let mut parts = token_unique_id.rsplitn(2, ':');
+    let market = parts.next().unwrap().to_string();
+    let nft_token_id = parts
+        .next()
+        .unwrap_or_else(|| ContractError::InvalidArgument(format!("Malformed locked token id {}.", token_unique_id)).panic())
+        .to_string();
+    (nft_token_id, market)
+}
+
+/// NFT contract of a listing, `None` for bundles.
+pub fn token_market(token_unique_id: &str) -> Option<AccountId> {
+    token_unique_id
+        .rfind(':')
+        .map(|index| token_unique_id[index + 1..].to_string())
+}
+
+impl LockedToken {
+    /// `token_id:market` ids of every NFT held as collateral for the loan.
+    pub fn collateral(&self) -> Vec<TokenId> {
+        if self.bundle.is_empty() {
+            vec![self.token_id.clone()]
+        } else {
+            self.bundle.clone()
+        }
+    }
+
+    /// Creditor of a loan, panics for listings nobody funded yet.
+    pub fn creditor_id(&self) -> AccountId {
+        self.creditor
+            .clone()
+            .unwrap_or_else(|| ContractError::NoCreditor(self.token_id.clone()).panic())
+    }
+
+    /// Start of the running loan, panics for listings nobody funded yet.
+    pub fn loan_start(&self) -> u64 {
+        self.start_time
+            .unwrap_or_else(|| ContractError::Internal(format!("Loan {} has no start time.", self.token_id)).panic())
+    }
+
+    /// Time interest was last accrued, panics for listings nobody funded yet.
+    pub fn accrued_since(&self) -> u64 {
+        self.accrued_at
+            .unwrap_or_else(|| ContractError::Internal(format!("Loan {} has no accrual time.", self.token_id)).panic())
+    }
+
+    pub fn from_listing(token_id: TokenId, owner_id: AccountId, listing: ListingRequest) -> Self {
+        Self {
+            token_id,
+            owner_id,
+            duration: listing.duration,
+            borrowed_money: listing.principal.0,
+            apr_bps: listing.apr_bps,
+            creditor: None,
+            start_time: None,
+            extra: listing.extra,
+            market_type: listing.market_type,
+            title: listing.title,
+            media: listing.media,
+            state: LockedTokenState::Sale,
+            auction: listing.auction.map(Auction::from_request),
+            interest_model: listing.interest_model,
+            outstanding_principal: listing.principal,
+            accrued_interest: U128(0),
+            accrued_at: None,
+            installments: listing.installments,
+            currency: listing.currency,
+            bundle: vec![],
+            liquidation: listing.liquidation,
+            grace_period: listing.grace_period,
+            late_fee: listing.late_fee,
+        }
+    }
+}
+
+/// JSON form of a `LockedToken`. Amounts, durations and timestamps are
+/// strings so JS clients don't lose precision.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct LockedTokenView {
+    pub token_id: TokenId,
+    pub owner_id: AccountId,
+    pub duration: U64,
+    pub borrowed_money: U128,
+    pub apr_bps: U64,
+    pub creditor: Option<AccountId>,
+    pub start_time: Option<U64>,
+    pub extra: String,
+    pub market_type: String,
+    pub title: String,
+    pub media: String,
+    pub state: LockedTokenState,
+    pub auction: Option<Auction>,
+    pub interest_model: InterestModel,
+    pub outstanding_principal: U128,
+    pub accrued_interest: U128,
+    pub accrued_at: Option<U64>,
+    pub installments: Vec<Installment>,
+    pub currency: Option<AccountId>,
+    pub bundle: Vec<TokenId>,
+    pub liquidation: Option<LiquidationRequest>,
+    pub grace_period: Option<U64>,
+    pub late_fee: Option<LateFee>,
+}
+
+impl LockedTokenView {
+    pub fn from_token(token: LockedToken) -> Self {
+        Self {
+            token_id: token.token_id,
+            owner_id: token.owner_id,
+            duration: U64(token.duration),
+            borrowed_money: U128(token.borrowed_money),
+            apr_bps: U64(token.apr_bps),
+            creditor: token.creditor,
+            start_time: token.start_time.map(U64),
+            extra: token.extra,
+            market_type: token.market_type,
+            title: token.title,
+            media: token.media,
+            state: token.state,
+            auction: token.auction,
+            interest_model: token.interest_model,
+            outstanding_principal: token.outstanding_principal,
+            accrued_interest: token.accrued_interest,
+            accrued_at: token.accrued_at.map(U64),
+            installments: token.installments,
+            currency: token.currency,
+            bundle: token.bundle,
+            liquidation: token.liquidation,
+            grace_period: token.grace_period.map(U64),
+            late_fee: token.late_fee,
+        }
+    }
+}
+
+/// Layout of `LockedToken` records stored by the first deployed version of
+/// the contract, only read by `migrate`. `apr` is a flat fee in percent.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct LegacyLockedToken {
     pub token_id: TokenId,
     pub owner_id: AccountId,
     pub duration: u64,
@@ -39,3 +214,48 @@ pub struct LockedToken {
     pub media: String,
     pub state: LockedTokenState,
 }
+
+impl LockedToken {
+    /// Legacy listings charged `apr` percent of the principal once, so they
+    /// become `FlatFee` loans. Running loans accrue that fee at their start
+    /// like loans funded after the upgrade.
+    pub fn from_legacy(token: LegacyLockedToken) -> Self {
+        let borrowed_money = u128::from_str(&token.borrowed_money).unwrap_or_else(|_| {
+            ContractError::Internal(format!("Token {} has malformed principal \"{}\".", token.token_id, token.borrowed_money))
+                .panic()
+        });
+        let apr_bps = token.apr * BPS_PER_PERCENT;
+        let interest_model = InterestModel::FlatFee;
+        let (accrued_interest, accrued_at) = if token.state == LockedTokenState::Locked {
+            (interest_model.interest(borrowed_money, apr_bps, 0), token.start_time)
+        } else {
+            (0, None)
+        };
+
+        Self {
+            token_id: token.token_id,
+            owner_id: token.owner_id,
+            duration: token.duration,
+            borrowed_money,
+            apr_bps,
+            creditor: token.creditor,
+            start_time: token.start_time,
+            extra: token.extra,
+            market_type: token.market_type,
+            title: token.title,
+            media: token.media,
+            state: token.state,
+            auction: None,
+            interest_model,
+            outstanding_principal: U128(borrowed_money),
+            accrued_interest: U128(accrued_interest),
+            accrued_at,
+            installments: vec![],
+            currency: None,
+            bundle: vec![],
+            liquidation: None,
+            grace_period: None,
+            late_fee: None,
+        }
+    }
+}
@@ -0,0 +1,568 @@
+use crate::*;
+use near_sdk::json_types::U128;
+use near_sdk::serde_json::{self, Value};
+
+const POOL_ACCOUNT_PREFIX: &str = "pool-";
+
+/// Liquidity lenders deposit to fund loans within the pool's limits without
+/// picking listings. Loans drawn from the pool have its pool account as
+/// creditor, repayments go back into `liquidity`.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct LendingPool {
+    pub id: u64,
+    /// NFT contract the pool lends against, any contract if not set.
+    pub market: Option<AccountId>,
+    /// Value of one NFT of the market the LTV limit applies to.
+    pub floor_price: U128,
+    pub max_ltv_bps: u64,
+    pub apr_bps: u64,
+    pub max_duration: u64,
+    pub interest_model: InterestModel,
+    /// NEAR available to draw or withdraw.
+    pub liquidity: U128,
+    /// Principal outstanding in running pool loans.
+    pub lent: U128,
+    /// Keeper-set prices of the defaulted NFTs the pool owns.
+    pub asset_value: U128,
+    /// Defaulted NFTs the pool owns that have no price yet.
+    pub unpriced_assets: u64,
+    pub total_shares: U128,
+}
+
+impl LendingPool {
+    pub fn max_principal(&self) -> u128 {
+        mul_div(self.floor_price.0, self.max_ltv_bps as u128, BPS_DENOMINATOR)
+    }
+
+    /// Defaulted NFTs count at their keeper-set price, unpriced ones for
+    /// nothing.
+    pub fn value(&self) -> u128 {
+        self.liquidity.0 + self.lent.0 + self.asset_value.0
+    }
+}
+
+/// Defaulted NFT owned by a pool. It can be bought once a keeper sets a price.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PoolAsset {
+    pub token_id: TokenId,
+    pub pool_id: u64,
+    pub price: Option<U128>,
+}
+
+/// `nft_approve` msg drawing a loan from a pool. Display fields are stored
+/// on the resulting `LockedToken` like those of a `ListingRequest`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PoolLoanRequest {
+    pub pool_id: u64,
+    pub principal: U128,
+    pub duration: u64,
+    #[serde(default)]
+    pub extra: String,
+    #[serde(default)]
+    pub market_type: String,
+    #[serde(default)]
+    pub title: String,
+    #[serde(default)]
+    pub media: String,
+}
+
+impl PoolLoanRequest {
+    /// Returns `None` for msgs that don't name a `pool_id`.
+    pub fn from_msg(msg: &str) -> Option<Self> {
+        let value = serde_json::from_str::<Value>(msg).ok()?;
+        value.get("pool_id")?;
+
+        Some(serde_json::from_value(value).unwrap_or_else(|e| {
+            ContractError::InvalidMsg(format!("Invalid pool loan msg: {}", e)).panic()
+        }))
+    }
+}
+
+/// Creditor id of the loans drawn from `pool_id`. It's a sub-account name of
+/// the contract that is never created, so nobody else can hold it.
+pub fn pool_account_id(pool_id: u64) -> AccountId {
+    format!("{}{}.{}", POOL_ACCOUNT_PREFIX, pool_id, env::current_account_id())
+}
+
+pub fn pool_id_from_account(account_id: &AccountId) -> Option<u64> {
+    let suffix = format!(".{}", env::current_account_id());
+    account_id
+        .strip_suffix(&suffix)?
+        .strip_prefix(POOL_ACCOUNT_PREFIX)?
+        .parse()
+        .ok()
+}
+
+#[near_bindgen]
+impl Contract {
+    pub fn create_pool(
+        &mut self,
+        market: Option<ValidAccountId>,
+        floor_price: U128,
+        max_ltv_bps: u64,
+        apr_bps: u64,
+        max_duration: u64,
+        interest_model: Option<InterestModel>,
+    ) -> u64 {
+        self.assert_admin();
+
+        let pool = LendingPool {
+            id: self.next_pool_id,
+            market: market.map(|market| market.into()),
+            floor_price,
+            max_ltv_bps,
+            apr_bps,
+            max_duration,
+            interest_model: interest_model.unwrap_or_default(),
+            liquidity: U128(0),
+            lent: U128(0),
+            asset_value: U128(0),
+            unpriced_assets: 0,
+            total_shares: U128(0),
+        };
+        assert_pool_terms(&pool);
+
+        self.next_pool_id += 1;
+        self.pools.insert(&pool.id, &pool);
+        pool.id
+    }
+
+    /// New terms only apply to loans drawn afterwards.
+    pub fn update_pool_terms(
+        &mut self,
+        pool_id: u64,
+        floor_price: U128,
+        max_ltv_bps: u64,
+        apr_bps: u64,
+        max_duration: u64,
+    ) {
+        self.assert_admin();
+
+        let mut pool = self.internal_get_pool(pool_id);
+        pool.floor_price = floor_price;
+        pool.max_ltv_bps = max_ltv_bps;
+        pool.apr_bps = apr_bps;
+        pool.max_duration = max_duration;
+        assert_pool_terms(&pool);
+
+        self.pools.insert(&pool_id, &pool);
+    }
+
+    /// Mints pool shares for the attached deposit at the current share value.
+    /// The depositor pays for the storage of the share balance.
+    #[payable]
+    pub fn deposit_to_pool(&mut self, pool_id: u64) -> U128 {
+        let account_id = env::predecessor_account_id();
+        let deposit = env::attached_deposit();
+        require(deposit > 0, || ContractError::WrongDeposit("Attach the amount to deposit.".to_string()));
+        self.assert_registered(&account_id);
+
+        let mut pool = self.internal_get_pool(pool_id);
+        require(pool.unpriced_assets == 0, || {
+            ContractError::WrongPhase(format!("Pool {} has defaulted NFTs without a price.", pool_id))
+        });
+        require(pool.total_shares.0 == 0 || pool.value() > 0, || {
+            ContractError::WrongPhase(format!("Pool {} has shares but no value left.", pool_id))
+        });
+        let shares = if pool.total_shares.0 == 0 {
+            deposit
+        } else {
+            mul_div(deposit, pool.total_shares.0, pool.value())
+        };
+        require(shares > 0, || ContractError::WrongDeposit("Deposit is worth less than one pool share.".to_string()));
+
+        pool.liquidity = U128(pool.liquidity.0 + deposit);
+        pool.total_shares = U128(pool.total_shares.0 + shares);
+        self.pools.insert(&pool_id, &pool);
+
+        let initial_storage_usage = env::storage_usage();
+        let key = (pool_id, account_id.clone());
+        let balance = self.pool_shares.get(&key).map_or(0, |balance| balance.0);
+        self.pool_shares.insert(&key, &U128(balance + shares));
+        self.internal_charge_storage(&account_id, initial_storage_usage);
+
+        U128(shares)
+    }
+
+    /// Burns `shares` for their part of the pool value, limited by the
+    /// liquidity not lent out. Blocked like deposits while a seized NFT has
+    /// no price.
+    pub fn withdraw_from_pool(&mut self, pool_id: u64, shares: U128) -> U128 {
+        let account_id = env::predecessor_account_id();
+        let key = (pool_id, account_id.clone());
+        let balance = self.pool_shares.get(&key).map_or(0, |balance| balance.0);
+        require(shares.0 > 0 && shares.0 <= balance, || {
+            ContractError::InsufficientFunds(format!("Can withdraw at most {} shares.", balance))
+        });
+
+        let mut pool = self.internal_get_pool(pool_id);
+        require(pool.unpriced_assets == 0, || {
+            ContractError::WrongPhase(format!("Pool {} has defaulted NFTs without a price.", pool_id))
+        });
+        let amount = mul_div(shares.0, pool.value(), pool.total_shares.0);
+        require(amount <= pool.liquidity.0, || {
+            ContractError::InsufficientFunds(format!("Only {} of the pool isn't lent out.", pool.liquidity.0))
+        });
+
+        pool.liquidity = U128(pool.liquidity.0 - amount);
+        pool.total_shares = U128(pool.total_shares.0 - shares.0);
+        self.pools.insert(&pool_id, &pool);
+
+        if balance == shares.0 {
+            let initial_storage_usage = env::storage_usage();
+            self.pool_shares.remove(&key);
+            self.internal_charge_storage(&account_id, initial_storage_usage);
+        } else {
+            self.pool_shares.insert(&key, &U128(balance - shares.0));
+        }
+
+        Promise::new(account_id).transfer(amount);
+        U128(amount)
+    }
+
+    /// Makes the NFT of an overdue pool loan an asset of the pool. Only
+    /// keepers can seize, deposits are blocked until a keeper prices it.
+    pub fn seize_pool_collateral(&mut self, token_id: TokenId) {
+        self.assert_keeper();
+        let token = self.internal_get_token(&token_id);
+        require_state(&token, LockedTokenState::Locked);
+        let pool_id = token
+            .creditor
+            .as_ref()
+            .and_then(pool_id_from_account)
+            .unwrap_or_else(|| ContractError::Unsupported(format!("Loan {} isn't drawn from a pool.", token_id)).panic());
+        require(self.check_is_token_delayed(token.clone()), || ContractError::NotOverdue(token_id.clone()));
+
+        let principal = self.pool_loans.remove(&token_id).map_or(0, |principal| principal.0);
+        let mut pool = self.internal_get_pool(pool_id);
+        pool.lent = U128(pool.lent.0 - principal);
+        pool.unpriced_assets += 1;
+        self.pools.insert(&pool_id, &pool);
+
+        ContractEvent::LoanDefaulted(TokenData::from_token(&token)).emit();
+        self.internal_drop_loan_extension(&token_id);
+        self.internal_remove_refinance_offer(&token);
+        self.internal_remove_locked_token(&token_id);
+        self.pool_assets.insert(&token_id, &PoolAsset { token_id: token_id.clone(), pool_id, price: None });
+    }
+
+    pub fn set_pool_asset_price(&mut self, token_id: TokenId, price: U128) {
+        self.assert_keeper();
+        let mut asset = self.internal_get_pool_asset(&token_id);
+        let mut pool = self.internal_get_pool(asset.pool_id);
+        match asset.price.replace(price) {
+            Some(previous_price) => pool.asset_value = U128(pool.asset_value.0 - previous_price.0),
+            None => pool.unpriced_assets -= 1,
+        }
+        pool.asset_value = U128(pool.asset_value.0 + price.0);
+        self.pools.insert(&asset.pool_id, &pool);
+        self.pool_assets.insert(&token_id, &asset);
+    }
+
+    /// Liquidates a pool asset. The asset is taken out of the pool while the
+    /// NFT transfer is in flight, its price counts towards the pool value
+    /// until the sale resolves. The excess deposit is refunded.
+    #[payable]
+    pub fn buy_pool_asset(&mut self, token_id: TokenId) {
+        let buyer = env::predecessor_account_id();
+        let deposit = env::attached_deposit();
+        let asset = self.internal_get_pool_asset(&token_id);
+        let price = asset
+            .price
+            .unwrap_or_else(|| ContractError::WrongPhase(format!("Pool asset {} isn't for sale.", token_id)).panic())
+            .0;
+        require(deposit >= price, || {
+            ContractError::WrongDeposit(format!("Attached deposit {} doesn't cover the price {}.", deposit, price))
+        });
+
+        self.pool_assets.remove(&token_id);
+        if deposit > price {
+            Promise::new(buyer.clone()).transfer(deposit - price);
+        }
+
+        transfer_bundle_item(&token_id, &buyer)
+            .then(ext_self::resolve_pool_asset_sale(asset,
+                                                    buyer,
+                                                    &env::current_account_id(),
+                                                    0,
+                                                    GAS_FOR_RESOLVE_NFT_RETURN));
+    }
+
+    #[private]
+    pub fn resolve_pool_asset_sale(&mut self, asset: PoolAsset, buyer: AccountId) -> bool {
+        let price = asset
+            .price
+            .unwrap_or_else(|| ContractError::Internal(format!("Pool asset {} was sold without a price.", asset.token_id)).panic())
+            .0;
+        if !is_promise_success() {
+            env::log(format!("Failed to transfer pool asset {} to {}, refunded.", asset.token_id, buyer).as_bytes());
+            self.pool_assets.insert(&asset.token_id, &asset);
+            Promise::new(buyer).transfer(price);
+            return false;
+        }
+
+        ContractEvent::CollateralReleased(ReleaseData {
+            token: TokenData::new(&asset.token_id, &pool_account_id(asset.pool_id), None),
+            receiver_id: buyer,
+            price: asset.price,
+        }).emit();
+
+        let mut pool = self.internal_get_pool(asset.pool_id);
+        pool.asset_value = U128(pool.asset_value.0 - price);
+        pool.liquidity = U128(pool.liquidity.0 + price);
+        self.pools.insert(&asset.pool_id, &pool);
+        true
+    }
+
+    /// Records the pool loan once the NFT is held, otherwise releases the
+    /// reserved liquidity.
+    #[private]
+    pub fn resolve_pool_loan(&mut self, token_id: TokenId, owner_id: AccountId, market: AccountId, request: PoolLoanRequest) -> bool {
+        let mut pool = self.internal_get_pool(request.pool_id);
+        let received = is_promise_success();
+        if !received || !self.has_storage_available(&owner_id, LISTING_STORAGE_BYTES) {
+            if received {
+                env::log(format!("Storage balance of {} no longer covers pool loan {}, NFT returned.", owner_id, token_id).as_bytes());
+                transfer_bundle_item(&token_id, &owner_id);
+            } else {
+                env::log(format!("Failed to receive NFT {} from {}, pool loan discarded.", token_id, owner_id).as_bytes());
+            }
+            pool.liquidity = U128(pool.liquidity.0 + request.principal.0);
+            pool.lent = U128(pool.lent.0 - request.principal.0);
+            self.pools.insert(&request.pool_id, &pool);
+            return false;
+        }
+
+        let listing = ListingRequest {
+            market: Some(market),
+            principal: request.principal,
+            apr_bps: pool.apr_bps,
+            apr: None,
+            duration: request.duration,
+            extra: request.extra,
+            market_type: request.market_type,
+            title: request.title,
+            media: request.media,
+            auction: None,
+            interest_model: pool.interest_model,
+            installments: vec![],
+            currency: None,
+            liquidation: None,
+            grace_period: None,
+            late_fee: None,
+        };
+        let locked_token = LockedToken::from_listing(token_id.clone(), owner_id, listing);
+        self.internal_add_locked_token(&locked_token);
+        self.pool_loans.insert(&token_id, &request.principal);
+        self.internal_lock_token(locked_token, &pool_account_id(request.pool_id), request.principal.0, pool.apr_bps);
+
+        true
+    }
+
+    pub fn get_pool(&self, pool_id: u64) -> Option<LendingPool> {
+        self.pools.get(&pool_id)
+    }
+
+    pub fn get_pools(&self, from_index: Option<u64>, limit: Option<u64>) -> Vec<LendingPool> {
+        paginate(self.pools.values_as_vector(), from_index, limit)
+    }
+
+    pub fn get_pool_shares(&self, pool_id: u64, account_id: AccountId) -> U128 {
+        self.pool_shares.get(&(pool_id, account_id)).unwrap_or(U128(0))
+    }
+
+    pub fn get_pool_asset(&self, token_id: TokenId) -> Option<PoolAsset> {
+        self.pool_assets.get(&token_id)
+    }
+}
+
+fn assert_pool_terms(pool: &LendingPool) {
+    require(pool.max_ltv_bps > 0 && pool.max_ltv_bps as u128 <= BPS_DENOMINATOR, || {
+        ContractError::InvalidArgument(format!("Pool max_ltv_bps must be within (0, {}].", BPS_DENOMINATOR))
+    });
+    validate_apr_bps(pool.apr_bps, "Pool");
+    require(pool.max_duration > MIN_LISTING_DURATION_SEC, || {
+        ContractError::InvalidArgument(format!("Pool max_duration must be longer than {} seconds.", MIN_LISTING_DURATION_SEC))
+    });
+    pool.interest_model.validate();
+}
+
+impl Contract {
+    fn internal_get_pool(&self, pool_id: u64) -> LendingPool {
+        self.pools
+            .get(&pool_id)
+            .unwrap_or_else(|| ContractError::NotFound(format!("pool {}", pool_id)).panic())
+    }
+
+    fn internal_get_pool_asset(&self, token_id: &TokenId) -> PoolAsset {
+        self.pool_assets
+            .get(token_id)
+            .unwrap_or_else(|| ContractError::NotFound(format!("pool asset {}", token_id)).panic())
+    }
+
+    /// Reserves the principal in the pool and pulls the approved NFT into the
+    /// contract, the loan starts in `resolve_pool_loan`.
+    pub(crate) fn internal_draw_pool_loan(
+        &mut self,
+        token_id: TokenId,
+        owner_id: AccountId,
+        approval_id: String,
+        market: AccountId,
+        request: PoolLoanRequest,
+    ) {
+        let mut pool = self.internal_get_pool(request.pool_id);
+        if let Some(pool_market) = &pool.market {
+            require(pool_market == &market, || {
+                ContractError::Unsupported(format!("Pool {} only lends against {}.", pool.id, pool_market))
+            });
+        }
+        require(request.principal.0 > 0, || ContractError::InvalidArgument("Loan principal must be positive.".to_string()));
+        require(request.principal.0 <= pool.max_principal(), || {
+            ContractError::LimitExceeded(format!("Pool {} lends at most {} against one NFT.", pool.id, pool.max_principal()))
+        });
+        require(request.principal.0 <= pool.liquidity.0, || {
+            ContractError::InsufficientFunds(format!("Pool {} has only {} available.", pool.id, pool.liquidity.0))
+        });
+        require(request.duration > MIN_LISTING_DURATION_SEC && request.duration <= pool.max_duration, || {
+            ContractError::InvalidArgument(format!("Loan duration must be within ({}, {}] seconds.",
+                                                   MIN_LISTING_DURATION_SEC,
+                                                   pool.max_duration))
+        });
+
+        pool.liquidity = U128(pool.liquidity.0 - request.principal.0);
+        pool.lent = U128(pool.lent.0 + request.principal.0);
+        self.pools.insert(&request.pool_id, &pool);
+
+        let token_unique_id = token_id.to_string() + ":" + &market;
+        marketplace::nft_transfer(ValidAccountId::try_from(env::current_account_id()).unwrap(),
+                                  token_id,
+                                  Some(approval_id),
+                                  None,
+                                  &market,
+                                  1,
+                                  GAS_FOR_NFT_TRANSFER)
+            .then(ext_self::resolve_pool_loan(token_unique_id,
+                                              owner_id,
+                                              market,
+                                              request,
+                                              &env::current_account_id(),
+                                              0,
+                                              GAS_FOR_RESOLVE_NFT_LOCK));
+    }
+
+    /// Takes a payment on a pool loan into the pool's liquidity. The principal
+    /// part is whatever the loan's outstanding principal dropped by, the loan
+    /// record is already updated when this is called. Interest capitalized by
+    /// an extension raises the principal the pool has lent instead.
+    pub(crate) fn internal_credit_pool(&mut self, pool_id: u64, token_id: &TokenId, amount: u128) {
+        let book_principal = self.pool_loans.get(token_id).map_or(0, |principal| principal.0);
+        let remaining_principal = match self.token_id_to_locked_token.get(token_id) {
+            Some(token) if token.state == LockedTokenState::Locked
+                && token.creditor == Some(pool_account_id(pool_id)) => token.outstanding_principal.0,
+            _ => 0,
+        };
+
+        let mut pool = self.internal_get_pool(pool_id);
+        pool.lent = U128(pool.lent.0 + remaining_principal.saturating_sub(book_principal)
+            - book_principal.saturating_sub(remaining_principal));
+        pool.liquidity = U128(pool.liquidity.0 + amount);
+        self.pools.insert(&pool_id, &pool);
+
+        if remaining_principal == 0 {
+            self.pool_loans.remove(token_id);
+        } else {
+            self.pool_loans.insert(token_id, &U128(remaining_principal));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use near_sdk::PromiseResult;
+
+    const LOAN_DURATION_SEC: u64 = 3_600;
+
+    /// Pool with 10 NEAR of `BOB`, whose 4 NEAR loan to `ALICE` was seized
+    /// unpriced.
+    fn setup_seized_pool() -> (Contract, u64, TokenId) {
+        let mut contract = setup_contract();
+        for account_id in &[ALICE, BOB, CHARLIE] {
+            register(&mut contract, account_id, NEAR);
+        }
+
+        set_context(OWNER, 0, 0);
+        let pool_id = contract.create_pool(None, U128(10 * NEAR), 5_000, 1_000, 10 * LOAN_DURATION_SEC, None);
+        set_context(BOB, 10 * NEAR, 0);
+        assert_eq!(contract.deposit_to_pool(pool_id), U128(10 * NEAR));
+
+        let msg = format!("{{\"pool_id\": {}, \"principal\": \"{}\", \"duration\": {}}}", pool_id, 4 * NEAR, LOAN_DURATION_SEC);
+        set_nft_context(ALICE, 0);
+        contract.nft_on_approve("1".to_string(), ALICE.to_string(), "0".to_string(), msg.clone());
+        let token_id = format!("1:{}", NFT_CONTRACT_ID);
+        set_callback_context(0, PromiseResult::Successful(vec![]));
+        assert!(contract.resolve_pool_loan(token_id.clone(),
+                                           ALICE.to_string(),
+                                           NFT_CONTRACT_ID.to_string(),
+                                           PoolLoanRequest::from_msg(&msg).unwrap()));
+
+        let pool = contract.get_pool(pool_id).unwrap();
+        assert_eq!((pool.liquidity.0, pool.lent.0), (6 * NEAR, 4 * NEAR));
+
+        set_context(OWNER, 0, LOAN_DURATION_SEC + 1);
+        contract.seize_pool_collateral(token_id.clone());
+        (contract, pool_id, token_id)
+    }
+
+    #[test]
+    fn shares_are_minted_and_burnt_at_the_value_after_seizure() {
+        let (mut contract, pool_id, token_id) = setup_seized_pool();
+        let pool = contract.get_pool(pool_id).unwrap();
+        assert_eq!((pool.lent.0, pool.unpriced_assets, pool.value()), (0, 1, 6 * NEAR));
+
+        set_context(OWNER, 0, LOAN_DURATION_SEC + 1);
+        contract.set_pool_asset_price(token_id, U128(2 * NEAR));
+        assert_eq!(contract.get_pool(pool_id).unwrap().value(), 8 * NEAR);
+
+        set_context(CHARLIE, 4 * NEAR, LOAN_DURATION_SEC + 1);
+        assert_eq!(contract.deposit_to_pool(pool_id), U128(5 * NEAR));
+
+        set_context(BOB, 0, LOAN_DURATION_SEC + 1);
+        assert_eq!(contract.withdraw_from_pool(pool_id, U128(5 * NEAR)), U128(4 * NEAR));
+        set_context(CHARLIE, 0, LOAN_DURATION_SEC + 1);
+        assert_eq!(contract.withdraw_from_pool(pool_id, U128(5 * NEAR)), U128(4 * NEAR));
+
+        let pool = contract.get_pool(pool_id).unwrap();
+        assert_eq!((pool.total_shares.0, pool.liquidity.0, pool.value()), (5 * NEAR, 2 * NEAR, 4 * NEAR));
+        assert_eq!(contract.get_pool_shares(pool_id, BOB.to_string()), U128(5 * NEAR));
+        assert_eq!(contract.get_pool_shares(pool_id, CHARLIE.to_string()), U128(0));
+    }
+
+    #[test]
+    #[should_panic(expected = "WrongPhase")]
+    fn deposits_wait_for_seized_assets_to_be_priced() {
+        let (mut contract, pool_id, _) = setup_seized_pool();
+        set_context(CHARLIE, 4 * NEAR, LOAN_DURATION_SEC + 1);
+        contract.deposit_to_pool(pool_id);
+    }
+
+    #[test]
+    #[should_panic(expected = "WrongPhase")]
+    fn withdrawals_wait_for_seized_assets_to_be_priced() {
+        let (mut contract, pool_id, _) = setup_seized_pool();
+        set_context(BOB, 0, LOAN_DURATION_SEC + 1);
+        contract.withdraw_from_pool(pool_id, U128(NEAR));
+    }
+
+    #[test]
+    #[should_panic(expected = "Unauthorized")]
+    fn only_keepers_seize() {
+        let mut contract = setup_contract();
+        set_context(BOB, 0, 0);
+        contract.seize_pool_collateral(format!("1:{}", NFT_CONTRACT_ID));
+    }
+}
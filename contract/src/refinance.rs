@@ -0,0 +1,224 @@
+use crate::*;
+use near_sdk::json_types::U128;
+
+/// Terms the borrower agrees to be refinanced at. Any lender paying off the
+/// current creditor within `max_principal` takes over the loan on these terms.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RefinanceOffer {
+    pub max_principal: U128,
+    pub apr_bps: u64,
+    pub duration: u64,
+    pub interest_model: InterestModel,
+    pub installments: Vec<Installment>,
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Publishes or replaces the borrower's standing refinance offer.
+    pub fn offer_refinance(
+        &mut self,
+        token_id: TokenId,
+        max_principal: U128,
+        apr_bps: u64,
+        duration: u64,
+        interest_model: Option<InterestModel>,
+        installments: Option<Vec<Installment>>,
+    ) {
+        let token = self.internal_get_refinanceable_token(&token_id);
+        require(env::predecessor_account_id() == token.owner_id, || {
+            ContractError::Unauthorized("Only borrower can offer a refinance.".to_string())
+        });
+
+        let offer = RefinanceOffer {
+            max_principal,
+            apr_bps,
+            duration,
+            interest_model: interest_model.unwrap_or_default(),
+            installments: installments.unwrap_or_default(),
+        };
+        validate_apr_bps(offer.apr_bps, "Refinance");
+        require(offer.duration > MIN_LISTING_DURATION_SEC, || {
+            ContractError::InvalidArgument(format!("Refinance duration must be longer than {} seconds.", MIN_LISTING_DURATION_SEC))
+        });
+        offer.interest_model.validate();
+        validate_installments(&offer.installments, offer.duration);
+
+        let initial_storage_usage = env::storage_usage();
+        self.refinance_offers.insert(&token_id, &offer);
+        self.internal_charge_storage(&token.owner_id, initial_storage_usage);
+        ContractEvent::OfferCreated(OfferData::new(OfferKind::RefinanceOffer,
+                                                   &token_id,
+                                                   &token.owner_id,
+                                                   offer.max_principal,
+                                                   offer.apr_bps,
+                                                   offer.duration)).emit();
+    }
+
+    pub fn cancel_refinance_offer(&mut self, token_id: TokenId) {
+        let token = self.internal_get_refinanceable_token(&token_id);
+        require(env::predecessor_account_id() == token.owner_id, || {
+            ContractError::Unauthorized("Only borrower can cancel a refinance offer.".to_string())
+        });
+
+        let offer = self
+            .internal_remove_refinance_offer(&token)
+            .unwrap_or_else(|| ContractError::NotFound(format!("refinance offer for {}", token_id)).panic());
+        ContractEvent::OfferCancelled(OfferData::new(OfferKind::RefinanceOffer,
+                                                     &token_id,
+                                                     &token.owner_id,
+                                                     offer.max_principal,
+                                                     offer.apr_bps,
+                                                     offer.duration)).emit();
+    }
+
+    pub fn get_refinance_offer(&self, token_id: TokenId) -> Option<RefinanceOffer> {
+        self.refinance_offers.get(&token_id)
+    }
+
+    /// Pays off the current creditor and takes over the loan on the borrower's
+    /// offered terms. The attached deposit becomes the new principal, whatever
+    /// exceeds the payoff goes to the borrower.
+    #[payable]
+    pub fn refinance_loan(&mut self, token_id: TokenId) {
+        let lender_id = env::predecessor_account_id();
+        let deposit = env::attached_deposit();
+        let mut token = self.internal_get_refinanceable_token(&token_id);
+
+        let offer = self
+            .refinance_offers
+            .get(&token_id)
+            .unwrap_or_else(|| ContractError::NotFound(format!("refinance offer for {}", token_id)).panic());
+
+        require(lender_id != token.owner_id, || ContractError::Unauthorized("Borrower can't refinance own loan.".to_string()));
+        self.assert_storage_available(&lender_id, CREDIT_STORAGE_BYTES);
+        require(!self.check_is_token_delayed(token.clone()), || ContractError::Overdue(token_id.clone()));
+
+        let quote = self.internal_repayment_quote(&token);
+        let payoff = quote.total_due.0;
+        let origination_fee = mul_div(deposit, self.origination_fee_bps as u128, BPS_DENOMINATOR);
+        require(deposit >= payoff + origination_fee, || {
+            ContractError::WrongDeposit(format!("Attached deposit {} doesn't cover the payoff {} and the origination fee {}.",
+                                                deposit,
+                                                payoff,
+                                                origination_fee))
+        });
+        require(deposit <= offer.max_principal.0, || {
+            ContractError::WrongDeposit(format!("Attached deposit {} exceeds the offered principal {}.",
+                                                deposit,
+                                                offer.max_principal.0))
+        });
+
+        let previous_creditor = token.creditor_id();
+        self.internal_remove_credit_token(&previous_creditor, &token_id);
+        self.internal_drop_loan_extension(&token_id);
+        self.internal_remove_refinance_offer(&token);
+
+        token.duration = offer.duration;
+        token.interest_model = offer.interest_model;
+        token.installments = offer.installments;
+        self.internal_start_loan(&mut token, &lender_id, deposit, offer.apr_bps);
+
+        ContractEvent::LoanRefinanced(RefinanceData {
+            loan: LoanData::from_token(&token),
+            previous_creditor_id: previous_creditor.clone(),
+            payoff: U128(payoff),
+        }).emit();
+
+        let interest_fee = self.internal_collect_fee(&token.currency,
+                                                     quote.interest.0 + quote.late_fee.0,
+                                                     self.interest_fee_bps);
+        self.internal_pay_creditor(&token, &previous_creditor, payoff - interest_fee);
+        self.internal_settle_fraction(&token_id, &token.owner_id);
+        self.internal_collect_fee(&token.currency, deposit, self.origination_fee_bps);
+        if deposit > payoff + origination_fee {
+            Promise::new(token.owner_id).transfer(deposit - payoff - origination_fee);
+        }
+    }
+}
+
+impl Contract {
+    fn internal_get_refinanceable_token(&self, token_id: &TokenId) -> LockedToken {
+        let token = self.internal_get_token(token_id);
+        require_state(&token, LockedTokenState::Locked);
+        assert_token_currency(&token, &None);
+        self.assert_not_liquidating(token_id);
+        token
+    }
+
+    /// Removes the borrower's refinance offer, releasing its storage.
+    pub(crate) fn internal_remove_refinance_offer(&mut self, token: &LockedToken) -> Option<RefinanceOffer> {
+        let initial_storage_usage = env::storage_usage();
+        let offer = self.refinance_offers.remove(&token.token_id);
+        self.internal_charge_storage(&token.owner_id, initial_storage_usage);
+        offer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    const LISTING_MSG: &str = r#"{"principal": "1000000000000000000000000", "apr_bps": 1000, "duration": 3600, "title": "Listing",
+        "interest_model": "FlatFee"}"#;
+
+    /// Loan of `BOB` to `ALICE` with 0.1 NEAR flat interest, `ALICE` offers
+    /// to refinance up to 3 NEAR.
+    fn setup_refinance_offer() -> (Contract, TokenId) {
+        let mut contract = setup_contract();
+        for account_id in &[ALICE, BOB, CHARLIE] {
+            register(&mut contract, account_id, NEAR);
+        }
+        let token_id = list_nft(&mut contract, ALICE, "1", LISTING_MSG);
+        fund_listing(&mut contract, BOB, &token_id, NEAR, 0);
+        set_context(ALICE, 0, 10);
+        contract.offer_refinance(token_id.clone(), U128(3 * NEAR), 500, 7_200, None, None);
+        (contract, token_id)
+    }
+
+    #[test]
+    fn refinancing_pays_off_the_creditor_and_the_rest_to_the_borrower() {
+        let (mut contract, token_id) = setup_refinance_offer();
+        set_context(CHARLIE, 2 * NEAR, 100);
+        contract.refinance_loan(token_id.clone());
+        assert_eq!(get_transfers(), vec![(BOB.to_string(), 11 * NEAR / 10), (ALICE.to_string(), 9 * NEAR / 10)]);
+
+        let token = contract.internal_get_token(&token_id);
+        assert_eq!((token.creditor_id(), token.borrowed_money, token.apr_bps, token.duration),
+                   (CHARLIE.to_string(), 2 * NEAR, 500, 7_200));
+        assert!(contract.get_refinance_offer(token_id.clone()).is_none());
+        assert!(contract.get_debtors_tokens(BOB.to_string(), None, None).is_empty());
+        assert_eq!(contract.get_debtors_tokens(CHARLIE.to_string(), None, None).len(), 1);
+    }
+
+    #[test]
+    fn refinancing_takes_the_interest_and_origination_fees() {
+        let (mut contract, token_id) = setup_refinance_offer();
+        set_context(OWNER, 0, 100);
+        contract.set_protocol_fees(100, 1_000);
+
+        set_context(CHARLIE, 2 * NEAR, 100);
+        contract.refinance_loan(token_id);
+        assert_eq!(get_transfers(), vec![(BOB.to_string(), 109 * NEAR / 100), (ALICE.to_string(), 88 * NEAR / 100)]);
+        assert_eq!(contract.get_treasury_balance(None).0, 3 * NEAR / 100);
+    }
+
+    #[test]
+    #[should_panic(expected = "WrongDeposit")]
+    fn refinancing_cant_exceed_the_offered_principal() {
+        let (mut contract, token_id) = setup_refinance_offer();
+        set_context(CHARLIE, 4 * NEAR, 100);
+        contract.refinance_loan(token_id);
+    }
+
+    #[test]
+    #[should_panic(expected = "NotFound")]
+    fn loans_without_an_offer_cant_be_refinanced() {
+        let (mut contract, token_id) = setup_refinance_offer();
+        set_context(ALICE, 0, 50);
+        contract.cancel_refinance_offer(token_id.clone());
+        set_context(CHARLIE, 2 * NEAR, 100);
+        contract.refinance_loan(token_id);
+    }
+}
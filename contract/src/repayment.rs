@@ -0,0 +1,380 @@
+use crate::*;
+use near_sdk::json_types::{U128, U64};
+
+pub const MAX_INSTALLMENTS: usize = 36;
+/// Longest grace period a listing or the global default can grant.
+pub const MAX_GRACE_PERIOD_SEC: u64 = 30 * 24 * 3600;
+/// Highest late fee charged per period, 10% of the outstanding principal.
+pub const MAX_LATE_FEE_BPS: u64 = 1_000;
+
+/// One step of a listing's repayment schedule.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Installment {
+    /// Seconds after the loan start by which this installment has to be paid.
+    pub due_after: U64,
+    /// Share of the principal due with this installment, in basis points.
+    pub principal_bps: u64,
+}
+
+/// Fee charged for every started `period` seconds a loan is past due, in
+/// basis points of the outstanding principal. A `period` of 1 charges per
+/// second, 86400 per day.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct LateFee {
+    pub bps: u64,
+    pub period: u64,
+}
+
+impl LateFee {
+    pub fn validate(&self) {
+        if self.period == 0 || self.period > MAX_GRACE_PERIOD_SEC {
+            ContractError::InvalidArgument(format!("Late fee period must be within (0, {}] seconds.", MAX_GRACE_PERIOD_SEC)).panic();
+        }
+        if self.bps > MAX_LATE_FEE_BPS {
+            ContractError::InvalidArgument(format!("Late fee bps can't exceed {}.", MAX_LATE_FEE_BPS)).panic();
+        }
+    }
+
+    pub fn fee(&self, principal: u128, seconds_past_due: u64) -> u128 {
+        let periods = seconds_past_due.div_ceil(self.period);
+        mul_div(principal, self.bps as u128 * periods as u128, BPS_DENOMINATOR)
+    }
+}
+
+pub fn validate_grace_period(grace_period: u64) {
+    if grace_period > MAX_GRACE_PERIOD_SEC {
+        ContractError::InvalidArgument(format!("Grace period can't exceed {} seconds.", MAX_GRACE_PERIOD_SEC)).panic();
+    }
+}
+
+/// Principal the borrower has to have repaid by `deadline` to keep up with
+/// the schedule.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct InstallmentQuote {
+    pub deadline: U64,
+    pub principal_due: U128,
+}
+
+/// Amount a borrower has to attach to `repay_loan` at the current block.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RepaymentQuote {
+    pub principal: U128,
+    pub interest: U128,
+    /// Charged while the loan is past due but within its grace period.
+    pub late_fee: U128,
+    /// Charged to the borrower on top of the debt. Protocol fees are taken
+    /// from the creditor's interest instead, so this is currently zero.
+    pub fees: U128,
+    pub total_due: U128,
+    /// Block timestamp in nanoseconds after which the loan is past due.
+    pub deadline: U64,
+    /// Block timestamp in nanoseconds after which the collateral can be
+    /// seized unless the loan is repaid.
+    pub grace_deadline: U64,
+    pub seconds_remaining: U64,
+    pub next_installment: Option<InstallmentQuote>,
+}
+
+/// Installments must be due in increasing order, end with the loan and add up
+/// to the whole principal.
+pub fn validate_installments(installments: &[Installment], duration: u64) {
+    if installments.is_empty() {
+        return;
+    }
+    if installments.len() > MAX_INSTALLMENTS {
+        ContractError::LimitExceeded(format!("At most {} installments are allowed.", MAX_INSTALLMENTS)).panic();
+    }
+
+    let mut last_due_after = 0;
+    let mut total_bps = 0;
+    for installment in installments {
+        if installment.due_after.0 <= last_due_after {
+            ContractError::InvalidArgument("Installments must be due in increasing order after the loan start.".to_string()).panic();
+        }
+        if installment.principal_bps == 0 {
+            ContractError::InvalidArgument("Installment principal_bps must be positive.".to_string()).panic();
+        }
+        last_due_after = installment.due_after.0;
+        total_bps += installment.principal_bps;
+    }
+
+    if last_due_after != duration {
+        ContractError::InvalidArgument(format!("Last installment must be due at the end of the loan, {} seconds.", duration)).panic();
+    }
+    if total_bps as u128 != BPS_DENOMINATOR {
+        ContractError::InvalidArgument(format!("Installments must add up to {} bps of principal, got {}.",
+                                               BPS_DENOMINATOR,
+                                               total_bps)).panic();
+    }
+}
+
+pub fn seconds_since(timestamp: u64) -> u64 {
+    Duration::from_nanos(env::block_timestamp() - timestamp).as_secs()
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Interest and late fees keep accruing until the repayment lands, so
+    /// callers should attach a small margin on top of `total_due`. The excess
+    /// is refunded.
+    pub fn get_repayment_quote(&self, token_id: TokenId) -> RepaymentQuote {
+        let token = self.internal_get_token(&token_id);
+
+        require_state(&token, LockedTokenState::Locked);
+        self.internal_repayment_quote(&token)
+    }
+
+    /// Grace period and late fee of listings that don't set their own. They
+    /// are fixed on a loan when it starts.
+    pub fn set_default_grace_terms(&mut self, grace_period: u64, late_fee: Option<LateFee>) {
+        self.assert_owner();
+        validate_grace_period(grace_period);
+        if let Some(late_fee) = &late_fee {
+            late_fee.validate();
+        }
+        self.default_grace_period = grace_period;
+        self.default_late_fee = late_fee;
+    }
+
+    pub fn get_default_grace_terms(&self) -> (u64, Option<LateFee>) {
+        (self.default_grace_period, self.default_late_fee.clone())
+    }
+
+    /// Pays down part of a loan. The payment covers the late fee and accrued
+    /// interest first and is forwarded to the creditor right away, less the
+    /// protocol's interest fee. A payment
+    /// covering everything due is handled as `repay_loan`.
+    #[payable]
+    pub fn repay_loan_partially(&mut self, token_id: TokenId) {
+        let deposit = env::attached_deposit();
+        let owner_id = env::predecessor_account_id();
+
+        let excess = self.internal_repay_loan_partially(&owner_id, token_id, deposit, None);
+        if excess > 0 {
+            Promise::new(owner_id).transfer(excess);
+        }
+    }
+}
+
+impl Contract {
+    /// Returns the part of `deposit` exceeding the amount due.
+    pub(crate) fn internal_repay_loan_partially(
+        &mut self,
+        owner_id: &AccountId,
+        token_id: TokenId,
+        deposit: u128,
+        currency: Option<AccountId>,
+    ) -> u128 {
+        let mut token = self.internal_get_token(&token_id);
+
+        require(&token.owner_id == owner_id, || ContractError::Unauthorized("Only borrower can repay the loan.".to_string()));
+        require_state(&token, LockedTokenState::Locked);
+        assert_token_currency(&token, &currency);
+        require(!self.check_is_token_delayed(token.clone()), || ContractError::Overdue(token_id.clone()));
+        require(deposit > 0, || ContractError::WrongDeposit("Attach the amount to repay.".to_string()));
+
+        let quote = self.internal_repayment_quote(&token);
+        if deposit >= quote.total_due.0 {
+            return self.internal_repay_loan(owner_id, token_id, deposit, currency);
+        }
+        require(deposit > quote.late_fee.0, || {
+            ContractError::WrongDeposit(format!("Attached deposit {} doesn't cover the late fee {}.", deposit, quote.late_fee.0))
+        });
+
+        self.internal_accrue_interest(&mut token);
+        let payment = deposit - quote.late_fee.0;
+        let interest_paid = std::cmp::min(payment, token.accrued_interest.0);
+        token.accrued_interest = U128(token.accrued_interest.0 - interest_paid);
+        token.outstanding_principal = U128(token.outstanding_principal.0 - (payment - interest_paid));
+
+        ContractEvent::LoanRepaid(RepaymentData {
+            token: TokenData::from_token(&token),
+            amount: U128(deposit),
+            outstanding_principal: token.outstanding_principal,
+            currency: token.currency.clone(),
+        }).emit();
+
+        let creditor = token.creditor_id();
+        self.token_id_to_locked_token.insert(&token_id, &token);
+
+        let fee = self.internal_collect_fee(&token.currency, quote.late_fee.0 + interest_paid, self.interest_fee_bps);
+        self.internal_pay_creditor(&token, &creditor, deposit - fee);
+        0
+    }
+
+    pub(crate) fn internal_repayment_quote(&self, token: &LockedToken) -> RepaymentQuote {
+        let principal = token.outstanding_principal.0;
+        let interest = token.accrued_interest.0 + self.internal_pending_interest(token);
+        let late_fee = self.internal_late_fee(token);
+        let fees = 0;
+
+        let elapsed_sec = self.get_loan_elapsed_sec(token);
+        let deadline = token.loan_start() + Duration::from_secs(token.duration).as_nanos() as u64;
+        let grace_period = Duration::from_secs(token.grace_period.unwrap_or(0)).as_nanos() as u64;
+
+        RepaymentQuote {
+            principal: U128(principal),
+            interest: U128(interest),
+            late_fee: U128(late_fee),
+            fees: U128(fees),
+            total_due: U128(principal + interest + late_fee + fees),
+            deadline: U64(deadline),
+            grace_deadline: U64(deadline + grace_period),
+            seconds_remaining: U64(token.duration.saturating_sub(elapsed_sec)),
+            next_installment: self.internal_next_installment(token),
+        }
+    }
+
+    /// Interest accrued since `accrued_at`. Flat fees are accrued in full when
+    /// the loan starts.
+    fn internal_pending_interest(&self, token: &LockedToken) -> u128 {
+        let elapsed_sec = seconds_since(token.accrued_since());
+        match token.interest_model {
+            InterestModel::FlatFee => 0,
+            InterestModel::Simple => token
+                .interest_model
+                .interest(token.outstanding_principal.0, token.apr_bps, elapsed_sec),
+            InterestModel::Compound { .. } => token
+                .interest_model
+                .interest(token.outstanding_principal.0 + token.accrued_interest.0, token.apr_bps, elapsed_sec),
+        }
+    }
+
+    pub(crate) fn internal_accrue_interest(&self, token: &mut LockedToken) {
+        token.accrued_interest = U128(token.accrued_interest.0 + self.internal_pending_interest(token));
+        token.accrued_at = Some(env::block_timestamp());
+    }
+
+    /// Principal that has to be repaid `elapsed_sec` seconds into the loan.
+    fn internal_required_repaid_principal(&self, token: &LockedToken, elapsed_sec: u64) -> u128 {
+        let due_bps: u64 = token
+            .installments
+            .iter()
+            .filter(|installment| installment.due_after.0 <= elapsed_sec)
+            .map(|installment| installment.principal_bps)
+            .sum();
+        mul_div(token.borrowed_money, due_bps as u128, BPS_DENOMINATOR)
+    }
+
+    fn internal_repaid_principal(&self, token: &LockedToken) -> u128 {
+        token.borrowed_money.saturating_sub(token.outstanding_principal.0)
+    }
+
+    /// Seconds since the earliest unmet deadline, the end of the loan or a
+    /// missed installment. `None` while the borrower is on schedule.
+    pub(crate) fn internal_seconds_past_due(&self, token: &LockedToken) -> Option<u64> {
+        let elapsed_sec = self.get_loan_elapsed_sec(token);
+        let due_after = if token.installments.is_empty() {
+            token.duration
+        } else {
+            let repaid = self.internal_repaid_principal(token);
+            token
+                .installments
+                .iter()
+                .map(|installment| installment.due_after.0)
+                .find(|due_after| self.internal_required_repaid_principal(token, *due_after) > repaid)?
+        };
+        elapsed_sec.checked_sub(due_after)
+    }
+
+    fn internal_late_fee(&self, token: &LockedToken) -> u128 {
+        match (&token.late_fee, self.internal_seconds_past_due(token)) {
+            (Some(late_fee), Some(seconds_past_due)) => late_fee.fee(token.outstanding_principal.0, seconds_past_due),
+            _ => 0,
+        }
+    }
+
+    fn internal_next_installment(&self, token: &LockedToken) -> Option<InstallmentQuote> {
+        let elapsed_sec = self.get_loan_elapsed_sec(token);
+        let installment = token
+            .installments
+            .iter()
+            .find(|installment| installment.due_after.0 > elapsed_sec)?;
+
+        let required = self.internal_required_repaid_principal(token, installment.due_after.0);
+        let deadline = token.loan_start() + Duration::from_secs(installment.due_after.0).as_nanos() as u64;
+
+        Some(InstallmentQuote {
+            deadline: U64(deadline),
+            principal_due: U128(required.saturating_sub(self.internal_repaid_principal(token))),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    const INSTALLMENTS_MSG: &str = r#"{"principal": "1000000000000000000000000", "apr_bps": 1000, "duration": 3600, "title": "Listing",
+        "interest_model": "FlatFee",
+        "installments": [{"due_after": "1800", "principal_bps": 5000}, {"due_after": "3600", "principal_bps": 5000}]}"#;
+
+    fn setup_loan() -> (Contract, TokenId) {
+        let mut contract = setup_contract();
+        register(&mut contract, ALICE, NEAR);
+        register(&mut contract, BOB, NEAR);
+        let token_id = list_nft(&mut contract, ALICE, "1", INSTALLMENTS_MSG);
+        fund_listing(&mut contract, BOB, &token_id, NEAR, 0);
+        (contract, token_id)
+    }
+
+    fn repay_partially(contract: &mut Contract, token_id: &TokenId, amount: u128, timestamp_sec: u64) {
+        set_context(ALICE, amount, timestamp_sec);
+        contract.repay_loan_partially(token_id.clone());
+    }
+
+    #[test]
+    fn partial_payment_settles_interest_before_principal() {
+        let (mut contract, token_id) = setup_loan();
+        repay_partially(&mut contract, &token_id, 3 * NEAR / 10, 100);
+        assert_eq!(get_transfers(), vec![(BOB.to_string(), 3 * NEAR / 10)]);
+
+        let token = contract.internal_get_token(&token_id);
+        assert_eq!((token.accrued_interest.0, token.outstanding_principal.0), (0, 8 * NEAR / 10));
+        let installment = contract.get_repayment_quote(token_id).next_installment.unwrap();
+        assert_eq!(installment.principal_due.0, 3 * NEAR / 10);
+        assert_eq!(installment.deadline.0, 1_800 * 1_000_000_000);
+    }
+
+    #[test]
+    fn missed_installment_defaults_the_loan() {
+        let (mut contract, token_id) = setup_loan();
+        repay_partially(&mut contract, &token_id, 3 * NEAR / 10, 100);
+
+        set_context(BOB, 0, 1_800);
+        assert!(contract.check_is_token_delayed(contract.internal_get_token(&token_id)));
+    }
+
+    #[test]
+    fn paid_installment_keeps_the_loan_current() {
+        let (mut contract, token_id) = setup_loan();
+        repay_partially(&mut contract, &token_id, 6 * NEAR / 10, 100);
+
+        set_context(BOB, 0, 3_000);
+        assert!(!contract.check_is_token_delayed(contract.internal_get_token(&token_id)));
+        let quote = contract.get_repayment_quote(token_id);
+        assert_eq!(quote.next_installment.unwrap().principal_due.0, NEAR / 2);
+        assert_eq!(quote.total_due.0, NEAR / 2);
+    }
+
+    #[test]
+    fn partial_payment_of_everything_due_repays_the_loan() {
+        let (mut contract, token_id) = setup_loan();
+        repay_partially(&mut contract, &token_id, 2 * NEAR, 100);
+
+        assert_eq!(contract.internal_get_token(&token_id).state, LockedTokenState::TransferToBorrower);
+        assert!(get_transfers().contains(&(ALICE.to_string(), 9 * NEAR / 10)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Overdue")]
+    fn defaulted_loan_cant_be_paid_down() {
+        let (mut contract, token_id) = setup_loan();
+        repay_partially(&mut contract, &token_id, NEAR / 10, 1_800);
+    }
+}
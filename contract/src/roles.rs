@@ -0,0 +1,71 @@
+use crate::*;
+
+/// Role checks. The owner is implicitly an admin and admins are implicitly
+/// keepers.
+#[near_bindgen]
+impl Contract {
+    pub fn set_owner(&mut self, owner_id: ValidAccountId) {
+        self.assert_owner();
+        self.owner_id = owner_id.into();
+    }
+
+    pub fn add_admin(&mut self, account_id: ValidAccountId) {
+        self.assert_owner();
+        self.admin_ids.insert(account_id.as_ref());
+    }
+
+    pub fn remove_admin(&mut self, account_id: ValidAccountId) {
+        self.assert_owner();
+        self.admin_ids.remove(account_id.as_ref());
+    }
+
+    pub fn add_keeper(&mut self, account_id: ValidAccountId) {
+        self.assert_admin();
+        self.keeper_ids.insert(account_id.as_ref());
+    }
+
+    pub fn remove_keeper(&mut self, account_id: ValidAccountId) {
+        self.assert_admin();
+        self.keeper_ids.remove(account_id.as_ref());
+    }
+
+    pub fn get_owner(&self) -> AccountId {
+        self.owner_id.clone()
+    }
+
+    pub fn get_admins(&self) -> Vec<AccountId> {
+        self.admin_ids.to_vec()
+    }
+
+    pub fn get_keepers(&self) -> Vec<AccountId> {
+        self.keeper_ids.to_vec()
+    }
+}
+
+impl Contract {
+    pub(crate) fn is_admin(&self, account_id: &AccountId) -> bool {
+        account_id == &self.owner_id || self.admin_ids.contains(account_id)
+    }
+
+    pub(crate) fn is_keeper(&self, account_id: &AccountId) -> bool {
+        self.is_admin(account_id) || self.keeper_ids.contains(account_id)
+    }
+
+    pub(crate) fn assert_owner(&self) {
+        require(env::predecessor_account_id() == self.owner_id, || {
+            ContractError::Unauthorized("Only owner can call this method.".to_string())
+        });
+    }
+
+    pub(crate) fn assert_admin(&self) {
+        require(self.is_admin(&env::predecessor_account_id()), || {
+            ContractError::Unauthorized("Only admin can call this method.".to_string())
+        });
+    }
+
+    pub(crate) fn assert_keeper(&self) {
+        require(self.is_keeper(&env::predecessor_account_id()), || {
+            ContractError::Unauthorized("Only keeper can call this method.".to_string())
+        });
+    }
+}
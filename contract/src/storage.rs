@@ -0,0 +1,362 @@
+use crate::*;
+use near_contract_standards::storage_management::{StorageBalance, StorageBalanceBounds, StorageManagement};
+use near_sdk::json_types::U128;
+use near_sdk::{assert_one_yocto, StorageUsage};
+
+/// Bytes of an account's own storage record, paid for on registration.
+pub const ACCOUNT_STORAGE_BYTES: StorageUsage = 200;
+/// Bytes a borrower needs available to list an NFT. Covers the listing and
+/// what it grows by once funded.
+pub const LISTING_STORAGE_BYTES: StorageUsage = 2_000;
+/// Bytes a lender needs available to become a creditor.
+pub const CREDIT_STORAGE_BYTES: StorageUsage = 600;
+/// Bytes the owner of an auction or fractionalized listing sets aside for
+/// what the listing grows by once funded, the loan is started by someone
+/// else's call.
+pub const LOAN_STORAGE_BYTES: StorageUsage = 300;
+
+/// NEP-145 balance of an account. `used_bytes` covers the account's own
+/// record, the records it owns and the storage reserved for its pending
+/// bids, offers and draft bundles.
+#[derive(BorshDeserialize, BorshSerialize, Clone, Debug)]
+pub struct AccountStorage {
+    pub total: u128,
+    pub used_bytes: StorageUsage,
+}
+
+impl AccountStorage {
+    pub fn available(&self) -> u128 {
+        self.total.saturating_sub(self.used_bytes as u128 * env::storage_byte_cost())
+    }
+}
+
+#[near_bindgen]
+impl StorageManagement for Contract {
+    #[payable]
+    fn storage_deposit(&mut self, account_id: Option<ValidAccountId>, registration_only: Option<bool>) -> StorageBalance {
+        let amount = env::attached_deposit();
+        let account_id = account_id.map_or_else(env::predecessor_account_id, |account_id| account_id.into());
+        let registration_only = registration_only.unwrap_or(false);
+        let min_balance = self.storage_balance_bounds().min.0;
+
+        let refund = if let Some(mut storage) = self.storage_accounts.get(&account_id) {
+            if registration_only {
+                amount
+            } else {
+                storage.total += amount;
+                self.storage_accounts.insert(&account_id, &storage);
+                0
+            }
+        } else {
+            require(amount >= min_balance, || {
+                ContractError::WrongDeposit(format!("The attached deposit is less than the minimum storage balance {}.", min_balance))
+            });
+            let total = if registration_only { min_balance } else { amount };
+            self.storage_accounts.insert(&account_id, &AccountStorage { total, used_bytes: ACCOUNT_STORAGE_BYTES });
+            amount - total
+        };
+        if refund > 0 {
+            Promise::new(env::predecessor_account_id()).transfer(refund);
+        }
+
+        self.internal_storage_balance_of(&account_id).unwrap()
+    }
+
+    #[payable]
+    fn storage_withdraw(&mut self, amount: Option<U128>) -> StorageBalance {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let mut storage = self.internal_get_account_storage(&account_id);
+
+        let available = storage.available();
+        let amount = amount.map_or(available, |amount| amount.0);
+        require(amount <= available, || {
+            ContractError::InsufficientFunds(format!("The amount is greater than the available storage balance {}.", available))
+        });
+
+        storage.total -= amount;
+        self.storage_accounts.insert(&account_id, &storage);
+        if amount > 0 {
+            Promise::new(account_id.clone()).transfer(amount);
+        }
+
+        self.internal_storage_balance_of(&account_id).unwrap()
+    }
+
+    /// Force unregistration isn't supported, an account can only unregister
+    /// once none of its listings or loans is stored.
+    #[payable]
+    fn storage_unregister(&mut self, force: Option<bool>) -> bool {
+        assert_one_yocto();
+        require(!force.unwrap_or(false), || ContractError::Unsupported("Force unregistration isn't supported.".to_string()));
+        let account_id = env::predecessor_account_id();
+
+        if let Some(storage) = self.storage_accounts.get(&account_id) {
+            require(storage.used_bytes == ACCOUNT_STORAGE_BYTES, || {
+                ContractError::Conflict("Can't unregister while listings or loans of the account are stored.".to_string())
+            });
+            self.storage_accounts.remove(&account_id);
+            Promise::new(account_id).transfer(storage.total);
+            true
+        } else {
+            false
+        }
+    }
+
+    fn storage_balance_bounds(&self) -> StorageBalanceBounds {
+        StorageBalanceBounds {
+            min: U128(ACCOUNT_STORAGE_BYTES as u128 * env::storage_byte_cost()),
+            max: None,
+        }
+    }
+
+    fn storage_balance_of(&self, account_id: ValidAccountId) -> Option<StorageBalance> {
+        self.internal_storage_balance_of(account_id.as_ref())
+    }
+}
+
+impl Contract {
+    fn internal_get_account_storage(&self, account_id: &AccountId) -> AccountStorage {
+        self.storage_accounts
+            .get(account_id)
+            .unwrap_or_else(|| ContractError::NotRegistered(account_id.clone()).panic())
+    }
+
+    fn internal_storage_balance_of(&self, account_id: &AccountId) -> Option<StorageBalance> {
+        self.storage_accounts.get(account_id).map(|storage| StorageBalance {
+            total: U128(storage.total),
+            available: U128(storage.available()),
+        })
+    }
+
+    pub(crate) fn assert_registered(&self, account_id: &AccountId) {
+        self.internal_get_account_storage(account_id);
+    }
+
+    /// Panics unless `account_id` can pay for `bytes` more storage.
+    pub(crate) fn assert_storage_available(&self, account_id: &AccountId, bytes: StorageUsage) {
+        let available = self.internal_get_account_storage(account_id).available();
+        let required = bytes as u128 * env::storage_byte_cost();
+        require(available >= required, || ContractError::InsufficientStorage {
+            account_id: account_id.clone(),
+            required: required - available,
+        });
+    }
+
+    /// Non-panicking `assert_storage_available` for callbacks, which have to
+    /// send the NFT back instead of failing.
+    pub(crate) fn has_storage_available(&self, account_id: &AccountId, bytes: StorageUsage) -> bool {
+        self.storage_accounts
+            .get(account_id)
+            .iter()
+            .any(|storage| storage.available() >= bytes as u128 * env::storage_byte_cost())
+    }
+
+    /// Sets `bytes` of the balance of `account_id` aside for a record a later
+    /// call stores, so that call can't fail on a withdrawn balance.
+    pub(crate) fn internal_reserve_storage(&mut self, account_id: &AccountId, bytes: StorageUsage) {
+        self.assert_storage_available(account_id, bytes);
+        let mut storage = self.internal_get_account_storage(account_id);
+        storage.used_bytes += bytes;
+        self.storage_accounts.insert(account_id, &storage);
+    }
+
+    /// Releases what `internal_reserve_storage` set aside.
+    pub(crate) fn internal_release_storage(&mut self, account_id: &AccountId, bytes: StorageUsage) {
+        if let Some(mut storage) = self.storage_accounts.get(account_id) {
+            storage.used_bytes = std::cmp::max(ACCOUNT_STORAGE_BYTES, storage.used_bytes.saturating_sub(bytes));
+            self.storage_accounts.insert(account_id, &storage);
+        }
+    }
+
+    /// Charges `account_id` for the storage used since `initial_storage_usage`,
+    /// or releases it if storage was freed. Panics if the account's balance
+    /// doesn't cover what it uses afterwards. Storage of unregistered
+    /// accounts, such as pool and share token creditors, is paid by the
+    /// contract.
+    pub(crate) fn internal_charge_storage(&mut self, account_id: &AccountId, initial_storage_usage: StorageUsage) {
+        if let Some(mut storage) = self.storage_accounts.get(account_id) {
+            let storage_usage = env::storage_usage();
+            storage.used_bytes = if storage_usage >= initial_storage_usage {
+                storage.used_bytes + (storage_usage - initial_storage_usage)
+            } else {
+                std::cmp::max(ACCOUNT_STORAGE_BYTES,
+                              storage.used_bytes.saturating_sub(initial_storage_usage - storage_usage))
+            };
+            let used = storage.used_bytes as u128 * env::storage_byte_cost();
+            require(storage.total >= used, || ContractError::InsufficientStorage {
+                account_id: account_id.clone(),
+                required: used - storage.total,
+            });
+            self.storage_accounts.insert(account_id, &storage);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use near_sdk::PromiseResult;
+
+    const LISTING_MSG: &str = r#"{"principal": "1000000000000000000000000", "apr_bps": 1000, "duration": 3600, "title": "Listing"}"#;
+
+    fn used_bytes(contract: &Contract, account_id: &str) -> StorageUsage {
+        contract.storage_accounts.get(&account_id.to_string()).unwrap().used_bytes
+    }
+
+    #[test]
+    fn storage_is_charged_while_listed_and_released_on_repayment() {
+        let mut contract = setup_contract();
+        register(&mut contract, ALICE, NEAR);
+        register(&mut contract, BOB, NEAR);
+
+        let token_id = list_nft(&mut contract, ALICE, "1", LISTING_MSG);
+        assert!(used_bytes(&contract, ALICE) > ACCOUNT_STORAGE_BYTES);
+
+        set_context(BOB, NEAR, 10);
+        contract.transfer_deposit_for_nft(token_id.clone());
+        assert!(used_bytes(&contract, ALICE) <= ACCOUNT_STORAGE_BYTES + LISTING_STORAGE_BYTES);
+        assert!(used_bytes(&contract, BOB) > ACCOUNT_STORAGE_BYTES);
+        assert!(used_bytes(&contract, BOB) <= ACCOUNT_STORAGE_BYTES + CREDIT_STORAGE_BYTES);
+
+        set_context(ALICE, 0, 1_000);
+        let total_due = contract.get_repayment_quote(token_id.clone()).total_due;
+        set_context(ALICE, total_due.0, 1_000);
+        contract.repay_loan(token_id.clone());
+        set_callback_context(1_000, PromiseResult::Successful(vec![]));
+        assert!(contract.resolve_nft_return(token_id.clone(), LockedTokenState::Locked, total_due));
+
+        assert!(contract.token_id_to_locked_token.get(&token_id).is_none());
+        assert_eq!(used_bytes(&contract, ALICE), ACCOUNT_STORAGE_BYTES);
+        assert_eq!(used_bytes(&contract, BOB), ACCOUNT_STORAGE_BYTES);
+
+        set_context(ALICE, 1, 1_000);
+        assert!(contract.storage_unregister(None));
+    }
+
+    #[test]
+    #[should_panic(expected = "InsufficientStorage")]
+    fn listing_needs_storage_balance() {
+        let mut contract = setup_contract();
+        let min_balance = contract.storage_balance_bounds().min.0;
+        register(&mut contract, ALICE, min_balance);
+        set_nft_context(ALICE, 0);
+        contract.nft_on_approve("1".to_string(), ALICE.to_string(), "0".to_string(), LISTING_MSG.to_string());
+    }
+
+    #[test]
+    fn listing_is_dropped_if_storage_is_withdrawn_before_the_callback() {
+        let mut contract = setup_contract();
+        register(&mut contract, ALICE, NEAR);
+        set_nft_context(ALICE, 0);
+        contract.nft_on_approve("1".to_string(), ALICE.to_string(), "0".to_string(), LISTING_MSG.to_string());
+
+        set_context(ALICE, 1, 0);
+        contract.storage_withdraw(None);
+
+        let token_id = format!("1:{}", NFT_CONTRACT_ID);
+        set_callback_context(0, PromiseResult::Successful(vec![]));
+        assert!(!contract.resolve_nft_lock(token_id.clone(), ALICE.to_string(), ListingRequest::from_msg(LISTING_MSG, false)));
+        assert!(contract.token_id_to_locked_token.get(&token_id).is_none());
+        assert_eq!(used_bytes(&contract, ALICE), ACCOUNT_STORAGE_BYTES);
+    }
+
+    #[test]
+    fn auction_settles_after_its_parties_withdrew_their_storage() {
+        let mut contract = setup_contract();
+        register(&mut contract, ALICE, NEAR);
+        register(&mut contract, BOB, NEAR);
+        register(&mut contract, CHARLIE, NEAR);
+
+        let msg = r#"{"principal": "1000000000000000000000000", "apr_bps": 1000, "duration": 3600, "title": "Listing",
+                      "auction": {"kind": "Apr", "bidding_duration": 100}}"#;
+        let token_id = list_nft(&mut contract, ALICE, "1", msg);
+
+        set_context(BOB, NEAR, 10);
+        contract.place_bid(token_id.clone(), Some(900));
+        assert_eq!(used_bytes(&contract, BOB), ACCOUNT_STORAGE_BYTES + CREDIT_STORAGE_BYTES);
+        set_context(CHARLIE, NEAR, 20);
+        contract.place_bid(token_id.clone(), Some(800));
+        assert_eq!(used_bytes(&contract, BOB), ACCOUNT_STORAGE_BYTES);
+
+        for account_id in &[ALICE, CHARLIE] {
+            set_context(account_id, 1, 30);
+            contract.storage_withdraw(None);
+        }
+
+        set_context(BOB, 0, 100);
+        contract.finalize_auction(token_id.clone());
+        let token = contract.token_id_to_locked_token.get(&token_id).unwrap();
+        assert_eq!(token.creditor, Some(CHARLIE.to_string()));
+        assert!(used_bytes(&contract, ALICE) <= ACCOUNT_STORAGE_BYTES + LISTING_STORAGE_BYTES);
+        assert!(used_bytes(&contract, CHARLIE) <= ACCOUNT_STORAGE_BYTES + CREDIT_STORAGE_BYTES);
+    }
+
+    #[test]
+    fn offers_drafts_and_pool_shares_are_charged_until_removed() {
+        let mut contract = setup_contract();
+        register(&mut contract, ALICE, NEAR);
+        register(&mut contract, BOB, NEAR);
+        let token_id = list_nft(&mut contract, ALICE, "1", LISTING_MSG);
+
+        set_context(BOB, NEAR, 10);
+        contract.make_counter_offer(token_id.clone(), 900, None);
+        assert_eq!(used_bytes(&contract, BOB), ACCOUNT_STORAGE_BYTES + CREDIT_STORAGE_BYTES);
+        contract.withdraw_counter_offer(token_id.clone());
+        assert_eq!(used_bytes(&contract, BOB), ACCOUNT_STORAGE_BYTES);
+
+        set_context(BOB, NEAR, 10);
+        let lender_offer_id = contract.create_lender_offer(account(NFT_CONTRACT_ID), None, 1000, 3600, None);
+        assert_eq!(used_bytes(&contract, BOB), ACCOUNT_STORAGE_BYTES + CREDIT_STORAGE_BYTES);
+        contract.cancel_lender_offer(lender_offer_id);
+        assert_eq!(used_bytes(&contract, BOB), ACCOUNT_STORAGE_BYTES);
+
+        set_context(OWNER, 0, 10);
+        let pool_id = contract.create_pool(None, U128(10 * NEAR), 5_000, 1_000, 36_000, None);
+        set_context(BOB, NEAR, 10);
+        contract.deposit_to_pool(pool_id);
+        assert!(used_bytes(&contract, BOB) > ACCOUNT_STORAGE_BYTES);
+        contract.withdraw_from_pool(pool_id, U128(NEAR));
+        assert_eq!(used_bytes(&contract, BOB), ACCOUNT_STORAGE_BYTES);
+
+        fund_listing(&mut contract, BOB, &token_id, NEAR, 10);
+        let listed_bytes = used_bytes(&contract, ALICE);
+        set_context(ALICE, 0, 20);
+        contract.offer_refinance(token_id.clone(), U128(2 * NEAR), 900, 7200, None, None);
+        assert!(used_bytes(&contract, ALICE) > listed_bytes);
+        contract.cancel_refinance_offer(token_id.clone());
+        assert_eq!(used_bytes(&contract, ALICE), listed_bytes);
+
+        contract.propose_loan_extension(token_id.clone(), 7200, U128(0), true);
+        assert!(used_bytes(&contract, ALICE) > listed_bytes);
+        contract.cancel_loan_extension(token_id.clone());
+        assert_eq!(used_bytes(&contract, ALICE), listed_bytes);
+
+        let bundle_id = contract.create_bundle();
+        assert_eq!(used_bytes(&contract, ALICE), listed_bytes + BUNDLE_STORAGE_BYTES);
+        contract.delete_bundle(bundle_id);
+        assert_eq!(used_bytes(&contract, ALICE), listed_bytes);
+    }
+
+    #[test]
+    fn cancelled_auction_releases_reserved_storage() {
+        let mut contract = setup_contract();
+        register(&mut contract, ALICE, NEAR);
+        register(&mut contract, BOB, NEAR);
+
+        let msg = r#"{"principal": "1000000000000000000000000", "apr_bps": 1000, "duration": 3600, "title": "Listing",
+                      "auction": {"kind": "Apr", "bidding_duration": 100}}"#;
+        let token_id = list_nft(&mut contract, ALICE, "1", msg);
+        set_context(BOB, NEAR, 10);
+        contract.place_bid(token_id.clone(), Some(900));
+
+        set_context(ALICE, 1, 20);
+        contract.transfer_nft_back(token_id.clone());
+        set_callback_context(20, PromiseResult::Successful(vec![]));
+        assert!(contract.resolve_nft_return(token_id.clone(), LockedTokenState::Sale, U128(0)));
+
+        assert_eq!(used_bytes(&contract, ALICE), ACCOUNT_STORAGE_BYTES);
+        assert_eq!(used_bytes(&contract, BOB), ACCOUNT_STORAGE_BYTES);
+    }
+}
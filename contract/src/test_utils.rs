@@ -0,0 +1,152 @@
+use crate::*;
+use near_sdk::serde::de::IgnoredAny;
+use near_sdk::serde_json::{self, Value};
+use near_sdk::test_utils::{get_created_receipts, get_logs, VMContextBuilder};
+use near_contract_standards::storage_management::StorageManagement;
+use near_sdk::{testing_env, MockedBlockchain, PromiseResult};
+
+pub const CONTRACT_ID: &str = "alevoro.near";
+pub const NFT_CONTRACT_ID: &str = "nft.near";
+pub const OWNER: &str = "owner.near";
+pub const ALICE: &str = "alice.near";
+pub const BOB: &str = "bob.near";
+pub const CHARLIE: &str = "charlie.near";
+
+pub const NEAR: u128 = 1_000_000_000_000_000_000_000_000;
+
+/// Mirror of the mocked receipts, which don't expose their fields. Only
+/// transfers keep their exact amount.
+#[derive(Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+struct MockedReceipt {
+    receiver_id: AccountId,
+    actions: Vec<MockedAction>,
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[allow(dead_code)]
+enum MockedAction {
+    CreateAccount,
+    DeployContract(IgnoredAny),
+    FunctionCall { method_name: String, args: String },
+    Transfer { deposit: u128 },
+    Stake(IgnoredAny),
+    AddKeyWithFullAccess(IgnoredAny),
+    AddKeyWithFunctionCall(IgnoredAny),
+    DeleteKey(IgnoredAny),
+    DeleteAccount(IgnoredAny),
+}
+
+fn created_receipts() -> Vec<MockedReceipt> {
+    get_created_receipts()
+        .iter()
+        .map(|receipt| serde_json::from_str(&serde_json::to_string(receipt).unwrap()).unwrap())
+        .collect()
+}
+
+/// Function calls of the current call as `(receiver, method_name, args)`.
+pub fn get_function_calls() -> Vec<(AccountId, String, Value)> {
+    created_receipts()
+        .into_iter()
+        .flat_map(|receipt| {
+            let receiver_id = receipt.receiver_id;
+            receipt.actions.into_iter().filter_map(move |action| match action {
+                MockedAction::FunctionCall { method_name, args } => {
+                    Some((receiver_id.clone(), method_name, serde_json::from_str(&args).unwrap_or(Value::Null)))
+                }
+                _ => None,
+            })
+        })
+        .collect()
+}
+
+/// NEAR transfers of the current call as `(receiver, amount)`.
+pub fn get_transfers() -> Vec<(AccountId, u128)> {
+    created_receipts()
+        .into_iter()
+        .flat_map(|receipt| {
+            let receiver_id = receipt.receiver_id;
+            receipt.actions.into_iter().filter_map(move |action| match action {
+                MockedAction::Transfer { deposit } => Some((receiver_id.clone(), deposit)),
+                _ => None,
+            })
+        })
+        .collect()
+}
+
+/// `EVENT_JSON` logs of the current call.
+pub fn get_events() -> Vec<Value> {
+    get_logs()
+        .iter()
+        .filter_map(|log| log.strip_prefix("EVENT_JSON:"))
+        .map(|event| serde_json::from_str(event).unwrap())
+        .collect()
+}
+
+pub fn account(account_id: &str) -> ValidAccountId {
+    ValidAccountId::try_from(account_id).unwrap()
+}
+
+fn builder(predecessor: &str, signer: &str, deposit: u128, timestamp_sec: u64) -> VMContextBuilder {
+    let mut builder = VMContextBuilder::new();
+    builder
+        .current_account_id(account(CONTRACT_ID))
+        .predecessor_account_id(account(predecessor))
+        .signer_account_id(account(signer))
+        .attached_deposit(deposit)
+        .block_timestamp(timestamp_sec * 1_000_000_000);
+    builder
+}
+
+/// Call by `predecessor` attaching `deposit`, `timestamp_sec` after genesis.
+pub fn set_context(predecessor: &str, deposit: u128, timestamp_sec: u64) {
+    testing_env!(builder(predecessor, predecessor, deposit, timestamp_sec).build());
+}
+
+/// `nft_on_approve` call of the NFT contract signed by `owner_id`.
+pub fn set_nft_context(owner_id: &str, timestamp_sec: u64) {
+    testing_env!(builder(NFT_CONTRACT_ID, owner_id, 0, timestamp_sec).build());
+}
+
+/// Callback of the contract on a promise that resolved to `result`.
+pub fn set_callback_context(timestamp_sec: u64, result: PromiseResult) {
+    set_callback_results(timestamp_sec, vec![result]);
+}
+
+/// Callback of the contract on joint promises that resolved to `results`.
+pub fn set_callback_results(timestamp_sec: u64, results: Vec<PromiseResult>) {
+    testing_env!(builder(CONTRACT_ID, CONTRACT_ID, 0, timestamp_sec).build(),
+                 Default::default(),
+                 Default::default(),
+                 Default::default(),
+                 results);
+}
+
+pub fn setup_contract() -> Contract {
+    set_context(OWNER, 0, 0);
+    Contract::new(account(OWNER), None, None)
+}
+
+pub fn register(contract: &mut Contract, account_id: &str, amount: u128) {
+    set_context(account_id, amount, 0);
+    contract.storage_deposit(None, None);
+}
+
+/// Funds the fixed-terms listing `token_id` with `principal` of `lender_id`.
+pub fn fund_listing(contract: &mut Contract, lender_id: &str, token_id: &TokenId, principal: u128, timestamp_sec: u64) {
+    set_context(lender_id, principal, timestamp_sec);
+    contract.transfer_deposit_for_nft(token_id.clone());
+}
+
+/// Lists NFT `nft_token_id` of `owner_id` with `msg` and resolves the NFT
+/// transfer. Returns the listing's token id.
+pub fn list_nft(contract: &mut Contract, owner_id: &str, nft_token_id: &str, msg: &str) -> TokenId {
+    set_nft_context(owner_id, 0);
+    contract.nft_on_approve(nft_token_id.to_string(), owner_id.to_string(), "0".to_string(), msg.to_string());
+
+    let token_id = format!("{}:{}", nft_token_id, NFT_CONTRACT_ID);
+    set_callback_context(0, PromiseResult::Successful(vec![]));
+    assert!(contract.resolve_nft_lock(token_id.clone(), owner_id.to_string(), ListingRequest::from_msg(msg, false)));
+    token_id
+}
@@ -0,0 +1,153 @@
+use crate::*;
+use near_sdk::json_types::U128;
+
+pub const MAX_ORIGINATION_FEE_BPS: u64 = 500;
+pub const MAX_INTEREST_FEE_BPS: u64 = 2_000;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ProtocolFees {
+    /// Share of the principal kept when a loan starts.
+    pub origination_fee_bps: u64,
+    /// Share of the interest and late fees kept when a loan is repaid.
+    pub interest_fee_bps: u64,
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Only loans started after the change pay the new origination fee, the
+    /// interest fee applies to every repayment made after it.
+    pub fn set_protocol_fees(&mut self, origination_fee_bps: u64, interest_fee_bps: u64) {
+        self.assert_owner();
+        require(origination_fee_bps <= MAX_ORIGINATION_FEE_BPS, || {
+            ContractError::InvalidArgument(format!("Origination fee can't exceed {} bps.", MAX_ORIGINATION_FEE_BPS))
+        });
+        require(interest_fee_bps <= MAX_INTEREST_FEE_BPS, || {
+            ContractError::InvalidArgument(format!("Interest fee can't exceed {} bps.", MAX_INTEREST_FEE_BPS))
+        });
+
+        self.origination_fee_bps = origination_fee_bps;
+        self.interest_fee_bps = interest_fee_bps;
+    }
+
+    pub fn get_protocol_fees(&self) -> ProtocolFees {
+        ProtocolFees {
+            origination_fee_bps: self.origination_fee_bps,
+            interest_fee_bps: self.interest_fee_bps,
+        }
+    }
+
+    /// Fees collected in `currency`, NEAR if not set.
+    pub fn get_treasury_balance(&self, currency: Option<AccountId>) -> U128 {
+        self.treasury.get(&currency_name(&currency).to_string()).unwrap_or(U128(0))
+    }
+
+    /// Fees collected per currency, NEAR fees are listed as "NEAR".
+    pub fn get_treasury(&self) -> Vec<(String, U128)> {
+        self.treasury.to_vec()
+    }
+
+    /// Sends collected fees to the owner, the whole balance if `amount` is
+    /// not set.
+    pub fn withdraw_treasury(&mut self, currency: Option<ValidAccountId>, amount: Option<U128>) -> U128 {
+        self.assert_owner();
+
+        let currency: Option<AccountId> = currency.map(|currency| currency.into());
+        let key = currency_name(&currency).to_string();
+        let balance = self.treasury.get(&key).map_or(0, |balance| balance.0);
+        let amount = amount.map_or(balance, |amount| amount.0);
+        require(amount > 0 && amount <= balance, || {
+            ContractError::InsufficientFunds(format!("Treasury holds {} {}.", balance, key))
+        });
+
+        if amount == balance {
+            self.treasury.remove(&key);
+        } else {
+            self.treasury.insert(&key, &U128(balance - amount));
+        }
+
+        let owner_id = self.owner_id.clone();
+        self.internal_pay(&currency, &owner_id, amount);
+        U128(amount)
+    }
+}
+
+impl Contract {
+    /// Moves `bps` of `amount` into the treasury and returns the fee taken.
+    pub(crate) fn internal_collect_fee(&mut self, currency: &Option<AccountId>, amount: u128, bps: u64) -> u128 {
+        let fee = mul_div(amount, bps as u128, BPS_DENOMINATOR);
+        if fee > 0 {
+            let key = currency_name(currency).to_string();
+            let balance = self.treasury.get(&key).map_or(0, |balance| balance.0);
+            self.treasury.insert(&key, &U128(balance + fee));
+        }
+        fee
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use near_sdk::PromiseResult;
+
+    const LISTING_MSG: &str = r#"{"principal": "1000000000000000000000000", "apr_bps": 1000, "duration": 3600, "title": "Listing",
+        "interest_model": "FlatFee"}"#;
+
+    /// Charges 1% of the principal and 10% of the interest, then funds and
+    /// repays a listing of ALICE by BOB.
+    fn setup_repaid_loan() -> Contract {
+        let mut contract = setup_contract();
+        register(&mut contract, ALICE, NEAR);
+        register(&mut contract, BOB, NEAR);
+        set_context(OWNER, 0, 0);
+        contract.set_protocol_fees(100, 1000);
+        let token_id = list_nft(&mut contract, ALICE, "1", LISTING_MSG);
+
+        fund_listing(&mut contract, BOB, &token_id, NEAR, 0);
+        assert_eq!(get_transfers(), vec![(ALICE.to_string(), NEAR * 99 / 100)]);
+        assert_eq!(contract.get_treasury_balance(None).0, NEAR / 100);
+
+        set_context(ALICE, NEAR * 11 / 10, 100);
+        contract.repay_loan(token_id.clone());
+        set_callback_context(100, PromiseResult::Successful(vec![]));
+        contract.resolve_nft_return(token_id, LockedTokenState::Locked, U128(NEAR * 11 / 10));
+        assert_eq!(get_transfers(), vec![(BOB.to_string(), NEAR * 109 / 100)]);
+        contract
+    }
+
+    #[test]
+    fn fees_are_collected_and_withdrawn_by_the_owner() {
+        let mut contract = setup_repaid_loan();
+        assert_eq!(contract.get_treasury(), vec![("NEAR".to_string(), U128(NEAR / 50))]);
+
+        set_context(OWNER, 0, 200);
+        assert_eq!(contract.withdraw_treasury(None, Some(U128(NEAR / 100))).0, NEAR / 100);
+        assert_eq!(get_transfers(), vec![(OWNER.to_string(), NEAR / 100)]);
+        assert_eq!(contract.withdraw_treasury(None, None).0, NEAR / 100);
+        assert!(contract.get_treasury().is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "InsufficientFunds")]
+    fn treasury_cant_be_overdrawn() {
+        let mut contract = setup_repaid_loan();
+        set_context(OWNER, 0, 200);
+        contract.withdraw_treasury(None, Some(U128(NEAR)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Unauthorized")]
+    fn only_owner_withdraws_the_treasury() {
+        let mut contract = setup_repaid_loan();
+        set_context(BOB, 0, 200);
+        contract.withdraw_treasury(None, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "InvalidArgument")]
+    fn fees_are_capped() {
+        let mut contract = setup_contract();
+        contract.set_protocol_fees(MAX_ORIGINATION_FEE_BPS, MAX_INTEREST_FEE_BPS + 1);
+    }
+}
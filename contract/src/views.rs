@@ -0,0 +1,295 @@
+use crate::*;
+use near_sdk::collections::Vector;
+use near_sdk::json_types::U128;
+
+/// List views return at most this many records per call.
+pub const MAX_PAGE_SIZE: u64 = 100;
+/// Index entries one `get_listings` call reads at most, whether they match
+/// or not.
+pub const MAX_LISTINGS_SCANNED: u64 = 500;
+
+/// Criteria of `get_listings`, unset fields match every listing. Ranges are
+/// inclusive, the principal is the originally borrowed amount.
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ListingFilter {
+    pub market: Option<AccountId>,
+    pub owner_id: Option<AccountId>,
+    pub state: Option<LockedTokenState>,
+    pub min_apr_bps: Option<u64>,
+    pub max_apr_bps: Option<u64>,
+    pub min_principal: Option<U128>,
+    pub max_principal: Option<U128>,
+    pub min_duration: Option<u64>,
+    pub max_duration: Option<u64>,
+}
+
+impl ListingFilter {
+    pub fn matches(&self, token: &LockedToken) -> bool {
+        self.market.iter().all(|market| token_market(&token.token_id).as_ref() == Some(market))
+            && self.owner_id.iter().all(|owner_id| &token.owner_id == owner_id)
+            && self.state.iter().all(|state| &token.state == state)
+            && in_range(token.apr_bps, self.min_apr_bps, self.max_apr_bps)
+            && in_range(token.borrowed_money, self.min_principal.map(|min| min.0), self.max_principal.map(|max| max.0))
+            && in_range(token.duration, self.min_duration, self.max_duration)
+    }
+}
+
+/// Matches of one `get_listings` call. The next page starts at `next_index`,
+/// which is `None` once the whole index was read.
+#[derive(Serialize, Deserialize, Default)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ListingPage {
+    pub listings: Vec<LockedTokenView>,
+    pub next_index: Option<u64>,
+}
+
+/// Whether `value` lies within the optional inclusive bounds.
+fn in_range<T: PartialOrd>(value: T, min: Option<T>, max: Option<T>) -> bool {
+    !matches!(min, Some(min) if value < min) && !matches!(max, Some(max) if value > max)
+}
+
+/// Items of `keys` at the positions `from_index`/`limit` select, `limit` is
+/// capped at `MAX_PAGE_SIZE`.
+pub(crate) fn paginate<T: BorshSerialize + BorshDeserialize>(
+    keys: &Vector<T>,
+    from_index: Option<u64>,
+    limit: Option<u64>,
+) -> Vec<T> {
+    let start = std::cmp::min(from_index.unwrap_or(0), keys.len());
+    let limit = std::cmp::min(limit.unwrap_or(MAX_PAGE_SIZE), MAX_PAGE_SIZE);
+    (start..std::cmp::min(start + limit, keys.len()))
+        .map(|index| keys.get(index).unwrap())
+        .collect()
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Up to `limit` listings matching `filter`. The narrowest index the
+    /// filter names, by owner, market or else state, is read from
+    /// `from_index` until `limit` listings matched or `MAX_LISTINGS_SCANNED`
+    /// entries were read. Pass the page's `next_index` as `from_index` of the
+    /// next call, a page can be short or empty while `next_index` is set.
+    pub fn get_listings(&self, filter: Option<ListingFilter>, from_index: Option<u64>, limit: Option<u64>) -> ListingPage {
+        let filter = filter.unwrap_or_default();
+        let limit = std::cmp::min(limit.unwrap_or(MAX_PAGE_SIZE), MAX_PAGE_SIZE) as usize;
+        self.internal_with_listing_index(&filter, |keys| {
+            let mut index = std::cmp::min(from_index.unwrap_or(0), keys.len());
+            let scan_end = std::cmp::min(index + MAX_LISTINGS_SCANNED, keys.len());
+            let mut listings = vec![];
+            while index < scan_end && listings.len() < limit {
+                if let Some(token) = keys.get(index).and_then(|token_id| self.token_id_to_locked_token.get(&token_id)) {
+                    if filter.matches(&token) {
+                        listings.push(LockedTokenView::from_token(token));
+                    }
+                }
+                index += 1;
+            }
+            ListingPage {
+                listings,
+                next_index: Some(index).filter(|index| *index < keys.len()),
+            }
+        })
+            .unwrap_or_default()
+    }
+
+    /// Size of the index `get_listings` reads for `filter`. Only the owner,
+    /// market and state of the filter narrow the index, so this is an upper
+    /// bound of the matching listings, not their number.
+    pub fn get_listing_count(&self, filter: Option<ListingFilter>) -> u64 {
+        self.internal_with_listing_index(&filter.unwrap_or_default(), |keys| keys.len())
+            .unwrap_or(0)
+    }
+
+    /// Listings in `state`. Those waiting for their NFTs to leave the contract
+    /// are in `Return`, `TransferToBorrower` or `TransferToCreditor`.
+    pub fn get_listings_by_state(&self, state: LockedTokenState, from_index: Option<u64>, limit: Option<u64>) -> Vec<LockedTokenView> {
+        self.listings_per_state
+            .get(&state)
+            .map_or_else(Vec::new, |token_ids| paginate(token_ids.as_vector(), from_index, limit))
+            .iter()
+            .map(|token_id| LockedTokenView::from_token(self.internal_get_token(token_id)))
+            .collect()
+    }
+
+    pub fn get_listing_count_by_state(&self, state: LockedTokenState) -> u64 {
+        self.listings_per_state
+            .get(&state)
+            .map_or(0, |token_ids| token_ids.len())
+    }
+}
+
+impl Contract {
+    /// Runs `f` on the index serving `filter`, `None` if the owner or market
+    /// has no listings.
+    fn internal_with_listing_index<R>(&self, filter: &ListingFilter, f: impl FnOnce(&Vector<TokenId>) -> R) -> Option<R> {
+        if let Some(owner_id) = &filter.owner_id {
+            self.tokens_stored_per_owner.get(owner_id).map(|token_ids| f(token_ids.as_vector()))
+        } else if let Some(market) = &filter.market {
+            self.listings_per_market.get(market).map(|token_ids| f(token_ids.as_vector()))
+        } else if let Some(state) = &filter.state {
+            self.listings_per_state.get(state).map(|token_ids| f(token_ids.as_vector()))
+        } else {
+            Some(f(self.listing_ids.as_vector()))
+        }
+    }
+
+    /// Adds a new listing to the global, per-market and per-state indexes.
+    pub(crate) fn internal_index_listing(&mut self, token: &LockedToken) {
+        let token_id = &token.token_id;
+        self.listing_ids.insert(token_id);
+        self.internal_index_state(token_id, &token.state);
+        if let Some(market) = token_market(token_id) {
+            let mut token_ids = self.listings_per_market.get(&market).unwrap_or_else(|| {
+                UnorderedSet::new(
+                    StorageKey::ListingsPerMarketInner {
+                        account_id_hash: hash_account_id(&market),
+                    }
+                        .try_to_vec()
+                        .unwrap(),
+                )
+            });
+            token_ids.insert(token_id);
+            self.listings_per_market.insert(&market, &token_ids);
+        }
+    }
+
+    pub(crate) fn internal_unindex_listing(&mut self, token: &LockedToken) {
+        let token_id = &token.token_id;
+        self.listing_ids.remove(token_id);
+        self.internal_unindex_state(token_id, &token.state);
+        if let Some(market) = token_market(token_id) {
+            if let Some(mut token_ids) = self.listings_per_market.get(&market) {
+                token_ids.remove(token_id);
+                if token_ids.is_empty() {
+                    self.listings_per_market.remove(&market);
+                } else {
+                    self.listings_per_market.insert(&market, &token_ids);
+                }
+            }
+        }
+    }
+
+    /// Moves `token` to `state` in the per-state index, storing the token is
+    /// left to the caller.
+    pub(crate) fn internal_set_state(&mut self, token: &mut LockedToken, state: LockedTokenState) {
+        if token.state != state {
+            self.internal_unindex_state(&token.token_id, &token.state);
+            self.internal_index_state(&token.token_id, &state);
+            token.state = state;
+        }
+    }
+
+    fn internal_index_state(&mut self, token_id: &TokenId, state: &LockedTokenState) {
+        let mut token_ids = self.listings_per_state.get(state).unwrap_or_else(|| {
+            UnorderedSet::new(
+                StorageKey::ListingsPerStateInner { state: state.clone() }
+                    .try_to_vec()
+                    .unwrap(),
+            )
+        });
+        token_ids.insert(token_id);
+        self.listings_per_state.insert(state, &token_ids);
+    }
+
+    fn internal_unindex_state(&mut self, token_id: &TokenId, state: &LockedTokenState) {
+        if let Some(mut token_ids) = self.listings_per_state.get(state) {
+            token_ids.remove(token_id);
+            if token_ids.is_empty() {
+                self.listings_per_state.remove(state);
+            } else {
+                self.listings_per_state.insert(state, &token_ids);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use near_sdk::PromiseResult;
+
+    fn listing_msg(apr_bps: u64) -> String {
+        format!(r#"{{"principal": "1000000000000000000000000", "apr_bps": {}, "duration": 3600, "title": "Listing"}}"#, apr_bps)
+    }
+
+    fn token_ids(page: &ListingPage) -> Vec<TokenId> {
+        page.listings.iter().map(|listing| listing.token_id.clone()).collect()
+    }
+
+    #[test]
+    fn filtered_pages_continue_where_the_scan_stopped() {
+        let mut contract = setup_contract();
+        register(&mut contract, ALICE, NEAR);
+        let token_ids_by_apr: Vec<TokenId> = [500, 1_500, 2_500]
+            .iter()
+            .enumerate()
+            .map(|(index, apr_bps)| list_nft(&mut contract, ALICE, &index.to_string(), &listing_msg(*apr_bps)))
+            .collect();
+
+        let filter = ListingFilter { min_apr_bps: Some(1_000), ..Default::default() };
+        let first = contract.get_listings(Some(filter.clone()), None, Some(1));
+        assert_eq!(token_ids(&first), vec![token_ids_by_apr[1].clone()]);
+        assert_eq!(first.next_index, Some(2));
+
+        let second = contract.get_listings(Some(filter.clone()), first.next_index, Some(1));
+        assert_eq!(token_ids(&second), vec![token_ids_by_apr[2].clone()]);
+        assert_eq!(second.next_index, None);
+
+        assert_eq!(contract.get_listing_count(Some(filter)), 3);
+        let others = ListingFilter { owner_id: Some(BOB.to_string()), ..Default::default() };
+        assert!(contract.get_listings(Some(others), None, None).listings.is_empty());
+    }
+
+    fn state_counts(contract: &Contract) -> Vec<u64> {
+        [LockedTokenState::Sale,
+         LockedTokenState::Return,
+         LockedTokenState::Locked,
+         LockedTokenState::TransferToCreditor,
+         LockedTokenState::TransferToBorrower]
+            .iter()
+            .map(|state| contract.get_listing_count_by_state(state.clone()))
+            .collect()
+    }
+
+    fn state_token_ids(contract: &Contract, state: LockedTokenState) -> Vec<TokenId> {
+        contract
+            .get_listings_by_state(state, None, None)
+            .into_iter()
+            .map(|listing| listing.token_id)
+            .collect()
+    }
+
+    #[test]
+    fn state_index_follows_transitions() {
+        let mut contract = setup_contract();
+        register(&mut contract, ALICE, NEAR);
+        register(&mut contract, BOB, NEAR);
+        let token_ids: Vec<TokenId> = (0..3)
+            .map(|index| list_nft(&mut contract, ALICE, &index.to_string(), &listing_msg(1_000)))
+            .collect();
+        assert_eq!(state_counts(&contract), vec![3, 0, 0, 0, 0]);
+        assert_eq!(contract.get_listings_by_state(LockedTokenState::Sale, Some(1), Some(1)).len(), 1);
+
+        fund_listing(&mut contract, BOB, &token_ids[0], NEAR, 0);
+        assert_eq!(state_counts(&contract), vec![2, 0, 1, 0, 0]);
+        assert_eq!(state_token_ids(&contract, LockedTokenState::Locked), vec![token_ids[0].clone()]);
+
+        set_context(ALICE, 0, 10);
+        contract.transfer_nft_back(token_ids[1].clone());
+        assert_eq!(state_counts(&contract), vec![1, 1, 1, 0, 0]);
+        set_callback_context(10, PromiseResult::Failed);
+        contract.resolve_nft_return(token_ids[1].clone(), LockedTokenState::Sale, U128(0));
+        assert_eq!(state_counts(&contract), vec![2, 0, 1, 0, 0]);
+
+        let repayment = contract.get_repayment_quote(token_ids[0].clone()).total_due.0;
+        set_context(ALICE, repayment, 10);
+        contract.repay_loan(token_ids[0].clone());
+        assert_eq!(state_token_ids(&contract, LockedTokenState::TransferToBorrower), vec![token_ids[0].clone()]);
+        set_callback_context(10, PromiseResult::Successful(vec![]));
+        contract.resolve_nft_return(token_ids[0].clone(), LockedTokenState::Locked, U128(repayment));
+        assert_eq!(state_counts(&contract), vec![2, 0, 0, 0, 0]);
+        assert!(!state_token_ids(&contract, LockedTokenState::Sale).contains(&token_ids[0]));
+    }
+}
@@ -9,6 +9,11 @@ const nearConfig = getConfig(process.env.NODE_ENV || "development");
 
 const GAS = "200000000000000";
 
+// Mirrors the contract's storage constants, see contract/src/storage.rs.
+const STORAGE_BYTE_COST = BigInt("10000000000000000000");
+const LISTING_STORAGE_BYTES = BigInt(2000);
+const CREDIT_STORAGE_BYTES = BigInt(600);
+
 export const {
     utils: {
         format: {
@@ -47,10 +52,11 @@ async function connect(nearConfig) {
     // Initializing our contract APIs by contract name and configuration.
     window.contract = await new nearAPI.Contract(window.walletConnection.account(), nearConfig.contractName, {
         // View methods are read-only – they don't modify the state, but usually return some value
-        viewMethods: ['get_debtors_tokens', 'get_locked_tokens', 'get_all_locked_tokens'],
+        viewMethods: ['get_debtors_tokens', 'get_locked_tokens', 'get_all_locked_tokens', 'get_repayment_quote',
+            'storage_balance_of', 'storage_balance_bounds'],
         // Change methods can modify the state, but you don't receive the returned value when called
         changeMethods: ['transfer_nft_back', 'repay_loan',
-            'transfer_deposit_for_nft', 'check_transfer_overdue_nft_to_creditor'],
+            'transfer_deposit_for_nft', 'check_transfer_overdue_nft_to_creditor', 'storage_deposit'],
         // Sender is the account ID to initialize transactions.
         // getAccountId() will return empty string if user is still unauthorized
         sender: window.walletConnection.getAccountId()
@@ -59,6 +65,28 @@ async function connect(nearConfig) {
 }
 
 
+// Listing and lending need a storage balance covering `bytes` on the contract.
+// Resolves to true if it already does, otherwise tops it up, which redirects
+// to the wallet, and the action has to be retried afterwards.
+async function ensureStorageBalance(bytes) {
+    const accountId = window.walletConnection.getAccountId();
+    const balance = await contract.storage_balance_of({account_id: accountId});
+    const required = bytes * STORAGE_BYTE_COST;
+    let deposit;
+    if (balance === null) {
+        const bounds = await contract.storage_balance_bounds();
+        deposit = BigInt(bounds.min) + required;
+    } else if (BigInt(balance.available) < required) {
+        deposit = required - BigInt(balance.available);
+    } else {
+        return true;
+    }
+    alert("A storage deposit is required first, retry once it is confirmed.");
+    await contract.storage_deposit({}, GAS, deposit.toString());
+    return false;
+}
+
+
 function updateUI() {
     console.log("update UI");
 
@@ -195,7 +223,7 @@ function showModalNft(id, nftState) {
         lockedBlock.style.display = 'block';
         borrowBlock.style.display = 'none';
 
-        document.querySelector('.apr').innerHTML = nft.apr;
+        document.querySelector('.apr').innerHTML = nft.apr_bps / 100;
         const curDur = secondsToTime(nft.duration);
         document.querySelector('.duration').innerHTML = `${curDur[0]} days, ${curDur[1]}:${curDur[2]}:${curDur[3]}`;
         document.querySelector('.amount').innerHTML = formatNearAmount(nft.borrowed_money);
@@ -205,8 +233,6 @@ function showModalNft(id, nftState) {
             document.querySelector('.creditor').innerHTML = nft.creditor;
             let curTime = Math.round(new Date().getTime() / 1000);
             let timeLeft = Number.parseInt(nft.duration) - (curTime - Number.parseInt((nft.start_time).toString().slice(0, 10)));
-            let multiplier = 1 + (Number.parseInt(nft.apr) / 100);
-            deposit = parseNearAmount((Number.parseFloat(formatNearAmount(nft.borrowed_money)) * multiplier).toString());
             if (nftState === 'MyLoans') {
                 document.querySelector('.title-modal-nft').innerHTML = "Debtor";
                 if (timeLeft > 0) {
@@ -241,7 +267,11 @@ function showModalNft(id, nftState) {
                     }
                 });
                 $('.modal-main-btn').off('click').click(function () {
-                    contract.repay_loan({token_id: id}, GAS, deposit.toString()).then(updateUI);
+                    contract.get_repayment_quote({token_id: id}).then(quote => {
+                        // Interest accrues until the transaction lands, the contract refunds the excess.
+                        const repayment = BigInt(quote.total_due) * BigInt(101) / BigInt(100);
+                        return contract.repay_loan({token_id: id}, GAS, repayment.toString());
+                    }).then(updateUI);
                 });
             }
         } else {
@@ -253,8 +283,10 @@ function showModalNft(id, nftState) {
                 } else {
                     document.querySelector('.title-modal-nft').innerHTML = "Lend";
                     document.querySelector('.modal-main-btn').innerHTML = "Lend";
-                    $('.modal-main-btn').off('click').click(function () {
-                        contract.transfer_deposit_for_nft({token_id: nft.token_id}, GAS, nft.borrowed_money).then(updateUI);
+                    $('.modal-main-btn').off('click').click(async function () {
+                        if (await ensureStorageBalance(CREDIT_STORAGE_BYTES)) {
+                            contract.transfer_deposit_for_nft({token_id: nft.token_id}, GAS, nft.borrowed_money).then(updateUI);
+                        }
                     });
                 }
             } else {
@@ -271,7 +303,7 @@ function showModalNft(id, nftState) {
             document.querySelector('.modal-main-btn').innerHTML = "Place offer";
             lockedBlock.style.display = 'none';
             borrowBlock.style.display = 'block';
-            $('.modal-main-btn').off('click').click(function () {
+            $('.modal-main-btn').off('click').click(async function () {
                 const amount = parseNearAmount(document.querySelector(".input-amount").value);
                 const apr = Number.parseInt(document.querySelector(".input-apr").value);
                 const days = Number.parseInt(document.querySelector(".input-days").value);
@@ -280,9 +312,20 @@ function showModalNft(id, nftState) {
                 const seconds = days * SEC_IN_DAY + hours * SEC_IN_HOUR + minutes * SEC_IN_MIN;
 
                 if (amount && seconds && apr && apr > 0 && seconds > 0) {
+                    if (!await ensureStorageBalance(LISTING_STORAGE_BYTES)) {
+                        return;
+                    }
                     const idAndContract = id.split(':');
-                    const params = [idAndContract[1], amount, apr, seconds, nft.extra, nft.type, nft.title, nft.url];
-                    const msg = params.join("!#@");
+                    const msg = JSON.stringify({
+                        market: idAndContract[1],
+                        principal: amount,
+                        apr_bps: apr * 100,
+                        duration: seconds,
+                        extra: nft.extra,
+                        market_type: nft.type,
+                        title: nft.title,
+                        media: nft.url
+                    });
                     console.log(msg);
                     window.walletConnection.account().functionCall(
                         idAndContract[1],
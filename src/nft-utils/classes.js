@@ -15,10 +15,10 @@ class NFT {
 
 class LockedNFT extends NFT {
     constructor(title, owner, token_id, url, extra, type,
-                apr, borrowed_money, duration, real_owner, state, creditor, start_time) {
+                apr_bps, borrowed_money, duration, real_owner, state, creditor, start_time) {
         super(title, owner, token_id, url, extra, type);
 
-        this.apr = apr;
+        this.apr_bps = apr_bps;
         this.borrowed_money = borrowed_money;
         this.duration = duration;
         this.real_owner = real_owner;
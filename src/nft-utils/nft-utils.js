@@ -8,7 +8,7 @@ function getNFTsInfo(res, con_name) {
     for (let nft of res) {
         console.log(nft);
         let curNFT = new LockedNFT(nft['title'], con_name, nft['token_id'], nft['media'], nft['extra'], nft['type'],
-             nft['apr'], nft['borrowed_money'], nft['duration'], nft['owner_id'], nft['state'],
+             nft['apr_bps'], nft['borrowed_money'], nft['duration'], nft['owner_id'], nft['state'],
             nft['creditor'], nft['start_time']);
 
         nfts.push(curNFT);